@@ -0,0 +1,149 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::reg::Error;
+
+mod memory;
+#[cfg(windows)]
+mod windows;
+
+#[cfg(windows)]
+pub(crate) use self::windows::WindowsRegistry;
+pub(crate) use memory::MemoryRegistry;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub(crate) enum Hive {
+    LocalMachine,
+    CurrentUser,
+}
+
+impl Display for Hive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Hive::LocalMachine => "HKEY_LOCAL_MACHINE",
+            Hive::CurrentUser => "HKEY_CURRENT_USER",
+        })
+    }
+}
+
+/// Which registry view a key is opened in. `Default` is whatever the running
+/// process gets without asking, the others map to `KEY_WOW64_64KEY` and
+/// `KEY_WOW64_32KEY` respectively.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub(crate) enum View {
+    Default,
+    Registry64,
+    Registry32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub(crate) struct KeyPath {
+    pub(crate) hive: Hive,
+    pub(crate) view: View,
+    pub(crate) path: String,
+}
+
+impl KeyPath {
+    pub(crate) fn new<S: Into<String>>(hive: Hive, view: View, path: S) -> KeyPath {
+        KeyPath {
+            hive,
+            view,
+            path: path.into(),
+        }
+    }
+
+    pub(crate) fn join(&self, name: &str) -> KeyPath {
+        KeyPath::new(self.hive, self.view, format!(r"{}\{}", self.path, name))
+    }
+
+    /// The path segments of this key as seen from the 64-bit view, so the
+    /// 32-bit view of `SOFTWARE` is redirected to `SOFTWARE\Wow6432Node`.
+    pub(crate) fn segments(&self) -> Vec<&str> {
+        let mut segments = self
+            .path
+            .split('\\')
+            .filter(|x| !x.is_empty())
+            .collect::<Vec<_>>();
+
+        let redirect = self.view == View::Registry32
+            && segments.len() > 1
+            && segments[0].eq_ignore_ascii_case("SOFTWARE")
+            && !segments[1].eq_ignore_ascii_case("Wow6432Node");
+
+        if redirect {
+            segments.insert(1, "Wow6432Node");
+        }
+
+        segments
+    }
+}
+
+impl Display for KeyPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, r"{}\{}", self.hive, self.path)?;
+        match self.view {
+            View::Default => Ok(()),
+            View::Registry64 => f.write_str(" (64-bit)"),
+            View::Registry32 => f.write_str(" (32-bit)"),
+        }
+    }
+}
+
+/// The subset of registry value types spelli reads or writes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Value {
+    None,
+    String(String),
+    U32(u32),
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::None => f.write_str("<None>"),
+            Value::String(s) => f.write_str(s),
+            Value::U32(x) => write!(f, "0x{:08x}", x),
+        }
+    }
+}
+
+/// Everything spelli needs from a registry. Paths are relative to the hive and
+/// opened in the view given by the `KeyPath`.
+pub(crate) trait RegistryBackend {
+    fn key_exists(&self, key: &KeyPath) -> Result<bool, Error>;
+
+    /// Creates the key and any missing parents. Creating an existing key is not an error.
+    fn create_key(&self, key: &KeyPath) -> Result<(), Error>;
+
+    /// Recursively deletes the key. Deleting a missing key is not an error.
+    fn delete_key(&self, key: &KeyPath) -> Result<(), Error>;
+
+    /// Returns `None` if either the key or the value does not exist.
+    fn value(&self, key: &KeyPath, name: &str) -> Result<Option<Value>, Error>;
+
+    /// Sets a value, creating the key first if required.
+    fn set_value(&self, key: &KeyPath, name: &str, value: &Value) -> Result<(), Error>;
+
+    /// Deletes a value. Deleting a missing value is not an error.
+    fn delete_value(&self, key: &KeyPath, name: &str) -> Result<(), Error>;
+
+    /// Enumerates all values of a key. Fails with `Error::NotFound` if the key does not exist.
+    fn values(&self, key: &KeyPath) -> Result<Vec<(String, Value)>, Error>;
+
+    /// Enumerates the names of all direct subkeys. Fails with `Error::NotFound` if the key does not exist.
+    fn subkeys(&self, key: &KeyPath) -> Result<Vec<String>, Error>;
+}
+
+/// The registry of the machine spelli is running on, if it has one.
+#[cfg(windows)]
+pub(crate) fn system() -> Option<Box<dyn RegistryBackend>> {
+    Some(Box::new(WindowsRegistry))
+}
+
+/// The registry of the machine spelli is running on, if it has one.
+#[cfg(not(windows))]
+pub(crate) fn system() -> Option<Box<dyn RegistryBackend>> {
+    None
+}
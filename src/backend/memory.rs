@@ -0,0 +1,190 @@
+use std::{cell::RefCell, collections::BTreeMap};
+
+use super::{Hive, KeyPath, RegistryBackend, Value};
+use crate::reg::Error;
+
+#[derive(Debug, Clone, Default)]
+struct Node {
+    name: String,
+    values: BTreeMap<String, (String, Value)>,
+}
+
+/// An in-memory registry tree that behaves like the registry of a 64-bit
+/// Windows installation: names are case-insensitive and the 32-bit view of
+/// `SOFTWARE` is redirected to `SOFTWARE\Wow6432Node`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MemoryRegistry {
+    keys: RefCell<BTreeMap<(Hive, String), Node>>,
+}
+
+impl MemoryRegistry {
+    pub(crate) fn new() -> MemoryRegistry {
+        Default::default()
+    }
+
+    fn id(hive: Hive, segments: &[&str]) -> (Hive, String) {
+        (hive, segments.join(r"\").to_lowercase())
+    }
+
+    fn not_found(key: &KeyPath) -> Error {
+        Error::NotFound(key.to_string())
+    }
+}
+
+impl RegistryBackend for MemoryRegistry {
+    fn key_exists(&self, key: &KeyPath) -> Result<bool, Error> {
+        let id = Self::id(key.hive, &key.segments());
+        Ok(self.keys.borrow().contains_key(&id))
+    }
+
+    fn create_key(&self, key: &KeyPath) -> Result<(), Error> {
+        let segments = key.segments();
+        let mut keys = self.keys.borrow_mut();
+
+        for i in 1..=segments.len() {
+            keys.entry(Self::id(key.hive, &segments[..i]))
+                .or_insert_with(|| Node {
+                    name: segments[i - 1].to_string(),
+                    values: BTreeMap::new(),
+                });
+        }
+
+        Ok(())
+    }
+
+    fn delete_key(&self, key: &KeyPath) -> Result<(), Error> {
+        let (hive, path) = Self::id(key.hive, &key.segments());
+        let prefix = format!(r"{}\", path);
+
+        self.keys
+            .borrow_mut()
+            .retain(|(h, p), _| *h != hive || (*p != path && !p.starts_with(&prefix)));
+
+        Ok(())
+    }
+
+    fn value(&self, key: &KeyPath, name: &str) -> Result<Option<Value>, Error> {
+        let id = Self::id(key.hive, &key.segments());
+        Ok(self
+            .keys
+            .borrow()
+            .get(&id)
+            .and_then(|node| node.values.get(&name.to_lowercase()))
+            .map(|(_, value)| value.clone()))
+    }
+
+    fn set_value(&self, key: &KeyPath, name: &str, value: &Value) -> Result<(), Error> {
+        self.create_key(key)?;
+
+        let id = Self::id(key.hive, &key.segments());
+        let mut keys = self.keys.borrow_mut();
+        let node = keys.get_mut(&id).ok_or_else(|| Self::not_found(key))?;
+        node.values
+            .insert(name.to_lowercase(), (name.to_string(), value.clone()));
+
+        Ok(())
+    }
+
+    fn delete_value(&self, key: &KeyPath, name: &str) -> Result<(), Error> {
+        let id = Self::id(key.hive, &key.segments());
+        if let Some(node) = self.keys.borrow_mut().get_mut(&id) {
+            node.values.remove(&name.to_lowercase());
+        }
+
+        Ok(())
+    }
+
+    fn values(&self, key: &KeyPath) -> Result<Vec<(String, Value)>, Error> {
+        let id = Self::id(key.hive, &key.segments());
+        let keys = self.keys.borrow();
+        let node = keys.get(&id).ok_or_else(|| Self::not_found(key))?;
+
+        Ok(node.values.values().cloned().collect())
+    }
+
+    fn subkeys(&self, key: &KeyPath) -> Result<Vec<String>, Error> {
+        let (hive, path) = Self::id(key.hive, &key.segments());
+        let keys = self.keys.borrow();
+
+        if !keys.contains_key(&(hive, path.clone())) {
+            return Err(Self::not_found(key));
+        }
+
+        let prefix = format!(r"{}\", path);
+        Ok(keys
+            .range((hive, prefix.clone())..)
+            .take_while(|((h, p), _)| *h == hive && p.starts_with(&prefix))
+            .filter(|((_, p), _)| !p[prefix.len()..].contains('\\'))
+            .map(|(_, node)| node.name.clone())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::View;
+
+    fn key(path: &str) -> KeyPath {
+        KeyPath::new(Hive::LocalMachine, View::Registry64, path)
+    }
+
+    #[test]
+    fn names_are_case_insensitive_but_keep_their_case() {
+        let registry = MemoryRegistry::new();
+        registry
+            .set_value(&key(r"SOFTWARE\WinDivvun"), "Count", &Value::U32(1))
+            .unwrap();
+
+        assert!(registry.key_exists(&key(r"software\windivvun")).unwrap());
+        assert_eq!(
+            registry
+                .value(&key(r"SOFTWARE\WINDIVVUN"), "count")
+                .unwrap(),
+            Some(Value::U32(1))
+        );
+        assert_eq!(
+            registry.values(&key(r"software\WinDivvun")).unwrap(),
+            vec![("Count".to_string(), Value::U32(1))]
+        );
+        assert_eq!(
+            registry.subkeys(&key("software")).unwrap(),
+            vec!["WinDivvun".to_string()]
+        );
+    }
+
+    #[test]
+    fn the_32_bit_view_is_redirected() {
+        let registry = MemoryRegistry::new();
+        let key32 = KeyPath::new(Hive::LocalMachine, View::Registry32, r"SOFTWARE\A");
+        registry.set_value(&key32, "x", &Value::U32(1)).unwrap();
+
+        assert!(!registry.key_exists(&key(r"SOFTWARE\A")).unwrap());
+        assert_eq!(
+            registry
+                .value(&key(r"SOFTWARE\Wow6432Node\A"), "x")
+                .unwrap(),
+            Some(Value::U32(1))
+        );
+    }
+
+    #[test]
+    fn deleting_a_key_deletes_its_subtree_only() {
+        let registry = MemoryRegistry::new();
+        registry.create_key(&key(r"SOFTWARE\A\B\C")).unwrap();
+        registry.create_key(&key(r"SOFTWARE\AB")).unwrap();
+
+        registry.delete_key(&key(r"SOFTWARE\A")).unwrap();
+
+        assert!(!registry.key_exists(&key(r"SOFTWARE\A\B")).unwrap());
+        assert!(registry.key_exists(&key(r"SOFTWARE\AB")).unwrap());
+        assert_eq!(
+            registry.subkeys(&key("SOFTWARE")).unwrap(),
+            vec!["AB".to_string()]
+        );
+        assert!(matches!(
+            registry.values(&key(r"SOFTWARE\A")),
+            Err(Error::NotFound(_))
+        ));
+    }
+}
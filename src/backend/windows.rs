@@ -0,0 +1,140 @@
+use std::convert::TryInto;
+
+use registry::{key, value, Data, RegKey, Security};
+
+use super::{Hive, KeyPath, RegistryBackend, Value, View};
+use crate::reg::Error;
+
+/// The real Windows registry.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct WindowsRegistry;
+
+fn hive(hive: Hive) -> registry::Hive {
+    match hive {
+        Hive::LocalMachine => registry::Hive::LocalMachine,
+        Hive::CurrentUser => registry::Hive::CurrentUser,
+    }
+}
+
+fn security(view: View, security: Security) -> Security {
+    match view {
+        View::Default => security,
+        View::Registry64 => security | Security::Wow6464Key,
+        View::Registry32 => security | Security::Wow6432Key,
+    }
+}
+
+fn from_data(data: Data) -> Option<Value> {
+    Some(match data {
+        Data::None => Value::None,
+        // spelli never writes expandable strings, so reading them as plain strings is enough.
+        Data::String(s) | Data::ExpandString(s) => Value::String(s.to_string_lossy()),
+        Data::U32(x) | Data::U32BE(x) => Value::U32(x),
+        _ => return None,
+    })
+}
+
+fn to_data(value: &Value) -> Result<Data, value::Error> {
+    Ok(match value {
+        Value::None => Data::None,
+        Value::String(s) => Data::String(s.as_str().try_into()?),
+        Value::U32(x) => Data::U32(*x),
+    })
+}
+
+impl WindowsRegistry {
+    fn open(&self, key: &KeyPath, sec: Security) -> Result<Option<RegKey>, Error> {
+        match hive(key.hive).open(key.path.as_str(), security(key.view, sec)) {
+            Ok(v) => Ok(Some(v)),
+            Err(key::Error::NotFound(_, _)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn create(&self, key: &KeyPath, sec: Security) -> Result<RegKey, Error> {
+        Ok(hive(key.hive).create(key.path.as_str(), security(key.view, sec))?)
+    }
+}
+
+impl RegistryBackend for WindowsRegistry {
+    fn key_exists(&self, key: &KeyPath) -> Result<bool, Error> {
+        Ok(self.open(key, Security::Read)?.is_some())
+    }
+
+    fn create_key(&self, key: &KeyPath) -> Result<(), Error> {
+        self.create(key, Security::AllAccess)?;
+        Ok(())
+    }
+
+    fn delete_key(&self, key: &KeyPath) -> Result<(), Error> {
+        match self.open(key, Security::AllAccess)? {
+            Some(regkey) => Ok(regkey.delete_self(true)?),
+            None => Ok(()),
+        }
+    }
+
+    fn value(&self, key: &KeyPath, name: &str) -> Result<Option<Value>, Error> {
+        let regkey = match self.open(key, Security::Read)? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+
+        match regkey.value(name) {
+            Ok(data) => Ok(from_data(data)),
+            Err(value::Error::NotFound(_, _)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn set_value(&self, key: &KeyPath, name: &str, value: &Value) -> Result<(), Error> {
+        let regkey = self.create(key, Security::Write)?;
+        regkey.set_value(name, &to_data(value)?)?;
+        Ok(())
+    }
+
+    fn delete_value(&self, key: &KeyPath, name: &str) -> Result<(), Error> {
+        let regkey = match self.open(key, Security::AllAccess)? {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+
+        match regkey.delete_value(name) {
+            Ok(()) | Err(value::Error::NotFound(_, _)) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn values(&self, key: &KeyPath) -> Result<Vec<(String, Value)>, Error> {
+        let regkey = self
+            .open(key, Security::Read)?
+            .ok_or_else(|| Error::NotFound(key.to_string()))?;
+
+        Ok(regkey
+            .values()
+            .flat_map(Result::ok)
+            .filter_map(|x| {
+                let (name, data) = x.into_inner();
+                let name = name.to_string_lossy();
+                match from_data(data) {
+                    Some(value) => Some((name, value)),
+                    None => {
+                        log::warn!("Unhandled data type for {} in {}", &name, key);
+                        None
+                    }
+                }
+            })
+            .collect())
+    }
+
+    fn subkeys(&self, key: &KeyPath) -> Result<Vec<String>, Error> {
+        let regkey = self
+            .open(key, Security::Read)?
+            .ok_or_else(|| Error::NotFound(key.to_string()))?;
+
+        Ok(regkey
+            .keys()
+            .flat_map(Result::ok)
+            .map(|x| x.to_string())
+            .collect())
+    }
+}
@@ -0,0 +1,283 @@
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::backend::{Hive, KeyPath, MemoryRegistry, RegistryBackend, Value, View};
+use crate::filesystem::{FileSystem, MemoryFileSystem};
+
+use crate::refresh::{self, RefreshOptions};
+use crate::reg::Scope;
+use crate::{format, libreoffice, reg};
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum Error {
+    #[error("A registry error occurred")]
+    Registry(#[from] reg::Error),
+
+    #[error("An IO error occurred")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid capture file")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Captures made before spelli scanned several speller roots have a single `speller_dir`.
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<PathBuf>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(PathBuf),
+        Many(Vec<PathBuf>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(path) => vec![path],
+        OneOrMany::Many(paths) => paths,
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct CapturedKey {
+    pub(crate) key: KeyPath,
+    pub(crate) values: Vec<(String, Value)>,
+}
+
+/// Everything spelli reads from a machine, so `list`, `detect` and `plan`
+/// can be run against it somewhere else.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Capture {
+    #[serde(alias = "speller_dir", deserialize_with = "one_or_many")]
+    pub(crate) speller_roots: Vec<PathBuf>,
+    keys: Vec<CapturedKey>,
+    pub(crate) files: MemoryFileSystem,
+}
+
+/// Records a key and its values, and optionally all of its subkeys. Missing keys are skipped.
+pub(crate) fn capture_key(
+    registry: &dyn RegistryBackend,
+    key: &KeyPath,
+    recursive: bool,
+    out: &mut Vec<CapturedKey>,
+) -> Result<(), reg::Error> {
+    let values = match registry.values(key) {
+        Err(reg::Error::NotFound(_)) => return Ok(()),
+        result => result?,
+    };
+
+    log::debug!("Capturing {}", key);
+    out.push(CapturedKey {
+        key: key.clone(),
+        values,
+    });
+
+    if recursive {
+        for name in registry.subkeys(key)? {
+            capture_key(registry, &key.join(&name), true, out)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Records a speller file by its archive entries, SHA-256 and header, so
+/// replays can validate it.
+fn capture_speller_file(fs: &dyn FileSystem, path: &Path, files: &mut MemoryFileSystem) {
+    files.add_speller_file(
+        path,
+        fs.archive_entries(path).ok(),
+        fs.sha256(path).ok(),
+        fs.header(path, format::BHFST_MAGIC.len()).ok(),
+    );
+}
+
+/// Records `dir` and everything below it. Unreadable directories are logged
+/// and left out rather than failing the whole capture.
+fn capture_files(fs: &dyn FileSystem, dir: &Path, files: &mut MemoryFileSystem) {
+    files.add_dir(dir);
+
+    let paths = match fs.read_dir(dir) {
+        Ok(v) => v,
+        Err(e) => {
+            log::error!("Not capturing {}: {}", dir.display(), e);
+            return;
+        }
+    };
+
+    for path in paths {
+        if fs.is_dir(&path) {
+            capture_files(fs, &path, &mut *files);
+        } else if path.file_name().map(|x| x == "speller.toml") == Some(true) {
+            files.add_file(&path, fs.read_to_string(&path).ok());
+        } else {
+            capture_speller_file(fs, &path, &mut *files);
+        }
+    }
+}
+
+impl Capture {
+    pub(crate) fn new(
+        registry: &dyn RegistryBackend,
+        fs: &dyn FileSystem,
+        speller_roots: &[PathBuf],
+        options: &RefreshOptions,
+    ) -> Result<Capture, Error> {
+        log::info!("Capturing machine state");
+        let mut keys = vec![];
+
+        for view in [View::Registry64, View::Registry32].iter() {
+            let key = KeyPath::new(Hive::LocalMachine, *view, refresh::KEY_UNINSTALL);
+            capture_key(registry, &key, true, &mut keys)?;
+        }
+
+        // Office detection looks for this key to decide if WOW64 is present
+        let wow64 = KeyPath::new(Hive::LocalMachine, View::Default, r"SOFTWARE\Wow6432Node");
+        capture_key(registry, &wow64, false, &mut keys)?;
+
+        for scope in [Scope::Machine, Scope::User].iter() {
+            capture_key(registry, &reg::windivvun_key(*scope), false, &mut keys)?;
+            capture_key(registry, &reg::spellers_key(*scope), true, &mut keys)?;
+            capture_key(
+                registry,
+                &reg::manual_spellers_key(*scope),
+                false,
+                &mut keys,
+            )?;
+        }
+        capture_key(registry, &reg::user_overrides_key(options), true, &mut keys)?;
+
+        for path in refresh::all_user_settings_paths(options) {
+            let key = KeyPath::new(Hive::LocalMachine, View::Registry64, path);
+            capture_key(registry, &key, true, &mut keys)?;
+        }
+
+        let mut files = MemoryFileSystem::new();
+        for root in speller_roots.iter() {
+            if fs.is_dir(root) {
+                capture_files(fs, root, &mut files);
+            }
+        }
+        for scope in [Scope::Machine, Scope::User].iter() {
+            for path in reg::manual_registrations(registry, *scope)?
+                .values()
+                .flatten()
+            {
+                let path = Path::new(path.as_str());
+                if fs.exists(path) {
+                    capture_speller_file(fs, path, &mut files);
+                }
+            }
+        }
+        if let Some(unopkg) = libreoffice::find_unopkg(registry, fs) {
+            files.add_file(&unopkg, None);
+        }
+        let dlls = &options.mso_dlls;
+        for dll in [&dlls.dll32, &dlls.dll64].iter() {
+            let path = Path::new(dll.as_str());
+            if fs.exists(path) {
+                files.add_file(path, None);
+            }
+        }
+
+        log::info!("Captured {} registry keys", keys.len());
+
+        Ok(Capture {
+            speller_roots: speller_roots.to_vec(),
+            keys,
+            files,
+        })
+    }
+
+    /// Rebuilds the captured registry keys in memory.
+    pub(crate) fn registry(&self) -> Result<MemoryRegistry, reg::Error> {
+        let registry = MemoryRegistry::new();
+
+        for captured in self.keys.iter() {
+            registry.create_key(&captured.key)?;
+            for (name, value) in captured.values.iter() {
+                registry.set_value(&captured.key, name, value)?;
+            }
+        }
+
+        Ok(registry)
+    }
+
+    pub(crate) fn load(path: &Path) -> Result<Capture, Error> {
+        Ok(serde_json::from_reader(File::open(path)?)?)
+    }
+
+    pub(crate) fn save(&self, path: &Path) -> Result<(), Error> {
+        serde_json::to_writer_pretty(File::create(path)?, self)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, FixedClock};
+
+    #[test]
+    fn replays_plan_like_the_captured_machine() {
+        let (registry, mut fs) = testing::machine();
+        testing::add_office(&registry);
+        testing::add_package(
+            &mut fs,
+            "se",
+            "[spellers]\nse = \"se.zhfst\"\n",
+            &["se.zhfst"],
+        );
+        let options = RefreshOptions::default();
+        let clock = FixedClock::at(1000);
+        let roots = testing::roots();
+        refresh::plan(&registry, &fs, &clock, &roots, Scope::Machine, &options)
+            .unwrap()
+            .apply(&registry)
+            .unwrap();
+        testing::add_package(
+            &mut fs,
+            "sma",
+            "[spellers]\nsma = \"sma.zhfst\"\n",
+            &["sma.zhfst"],
+        );
+
+        let path = testing::temp_dir("capture").join("capture.json");
+        Capture::new(&registry, &fs, &roots, &options)
+            .unwrap()
+            .save(&path)
+            .unwrap();
+        let capture = Capture::load(&path).unwrap();
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        clock.set(2000);
+        let expected = refresh::plan(&registry, &fs, &clock, &roots, Scope::Machine, &options);
+        let replayed = refresh::plan(
+            &capture.registry().unwrap(),
+            &capture.files,
+            &clock,
+            &capture.speller_roots,
+            Scope::Machine,
+            &options,
+        );
+        assert!(!expected.as_ref().unwrap().steps.is_empty());
+        assert_eq!(replayed.unwrap(), expected.unwrap());
+    }
+
+    #[test]
+    fn nested_speller_files_are_captured() {
+        let (registry, mut fs) = testing::machine();
+        let dir = testing::add_package(&mut fs, "se", "[spellers]\nse = \"se/se.zhfst\"\n", &[]);
+        fs.add_dir(&dir.join("se"));
+        testing::add_zhfst(&mut fs, &dir.join("se").join("se.zhfst"));
+
+        let roots = testing::roots();
+        let capture = Capture::new(&registry, &fs, &roots, &RefreshOptions::default()).unwrap();
+        let path = dir.join("se").join("se.zhfst");
+        assert_eq!(
+            capture.files.archive_entries(&path).unwrap(),
+            fs.archive_entries(&path).unwrap()
+        );
+    }
+}
@@ -0,0 +1,56 @@
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::filesystem::FileSystem;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum Error {
+    #[error("Could not read {0} to check its SHA-256")]
+    Io(PathBuf, #[source] std::io::Error),
+
+    #[error("'{0}' is not a SHA-256, which is 64 hexadecimal digits")]
+    Invalid(String),
+
+    #[error(
+        "SHA-256 mismatch for {path}: speller.toml declares {expected}, the file has {actual}"
+    )]
+    Mismatch {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+}
+
+/// The SHA-256 of `data`, in lowercase hex.
+pub(crate) fn sha256(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect()
+}
+
+/// Checks a declared SHA-256 is well-formed, and returns it in lowercase.
+pub(crate) fn parse(sha256: &str) -> Result<String, Error> {
+    if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(Error::Invalid(sha256.to_string()));
+    }
+    Ok(sha256.to_ascii_lowercase())
+}
+
+/// Checks the file at `path` has the SHA-256 `expected`.
+pub(crate) fn verify(fs: &dyn FileSystem, path: &Path, expected: &str) -> Result<(), Error> {
+    let expected = parse(expected)?;
+    let actual = fs
+        .sha256(path)
+        .map_err(|e| Error::Io(path.to_path_buf(), e))?;
+
+    if actual != expected {
+        return Err(Error::Mismatch {
+            path: path.to_path_buf(),
+            expected,
+            actual,
+        });
+    }
+    Ok(())
+}
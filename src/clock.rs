@@ -0,0 +1,31 @@
+use std::convert::TryInto;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Where spelli gets the current time from when writing Count and related markers.
+pub(crate) trait Clock {
+    fn now(&self) -> SystemTime;
+}
+
+/// The clock of the machine spelli is running on.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Seconds since 2020-01-01, the units of Count. A clock set before 2020 reads
+/// as 0 rather than failing; callers keep Count increasing regardless.
+pub(crate) fn counter(clock: &dyn Clock) -> u32 {
+    // Our epoch starts at 2020-01-01.
+    let our_epoch = UNIX_EPOCH + Duration::from_secs(1577836800);
+    match clock.now().duration_since(our_epoch) {
+        Ok(elapsed) => elapsed.as_secs().try_into().unwrap_or(u32::MAX),
+        Err(_) => {
+            log::warn!("The system clock is set before 2020");
+            0
+        }
+    }
+}
@@ -0,0 +1,406 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::backend::RegistryBackend;
+use crate::format::Format;
+use crate::mso::MsoDlls;
+use crate::refresh::RefreshOptions;
+use crate::reg::{self, Scope};
+
+/// Environment variable listing extra speller roots, separated like `PATH`.
+pub(crate) const SPELLER_DIRS_ENV: &str = "SPELLI_SPELLER_DIRS";
+
+const CONFIG_FILE: &str = "spelli.toml";
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum Error {
+    #[error("Could not read configuration file {0}")]
+    Io(PathBuf, #[source] std::io::Error),
+
+    #[error("Invalid configuration file {0}")]
+    Parse(PathBuf, #[source] toml::de::Error),
+}
+
+/// The contents of a spelli.toml. Everything is optional.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    speller_dir: Option<PathBuf>,
+    #[serde(default)]
+    speller_roots: Vec<PathBuf>,
+    mso_dll_32: Option<String>,
+    mso_dll_64: Option<String>,
+    proof_tool_path: Option<String>,
+    user_settings_name: Option<String>,
+    log_dir: Option<PathBuf>,
+    libreoffice_extension_id: Option<String>,
+    speller_formats: Option<Vec<Format>>,
+    #[serde(default)]
+    refresh: RefreshSection,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RefreshSection {
+    tombstone_retention_days: Option<u64>,
+}
+
+/// Where a configuration value came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Source {
+    Default,
+    File(PathBuf),
+    Environment(&'static str),
+    CommandLine,
+    /// Found on this machine, such as the DLLs of the installed WinDivvun.
+    Detected,
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(path) => write!(f, "{}", path.display()),
+            Source::Environment(name) => write!(f, "environment variable {}", name),
+            Source::CommandLine => write!(f, "command line"),
+            Source::Detected => write!(f, "detected"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Setting<T> {
+    pub(crate) value: T,
+    pub(crate) source: Source,
+}
+
+impl<T> Setting<T> {
+    fn default(value: T) -> Setting<T> {
+        Setting {
+            value,
+            source: Source::Default,
+        }
+    }
+
+    fn set(&mut self, value: Option<T>, source: &Source) {
+        if let Some(value) = value {
+            self.value = value;
+            self.source = source.clone();
+        }
+    }
+}
+
+/// Settings given on the command line, which win over everything else.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Overrides {
+    pub(crate) speller_roots: Vec<PathBuf>,
+    pub(crate) mso_dll_32: Option<String>,
+    pub(crate) mso_dll_64: Option<String>,
+    pub(crate) tombstone_retention_days: Option<u64>,
+}
+
+/// The effective configuration: built-in defaults, then spelli.toml, then the
+/// environment, then the command line.
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    /// The configuration file that was read, if any.
+    pub(crate) file: Option<PathBuf>,
+    /// The scope's own speller directory, scanned after every other root.
+    pub(crate) speller_dir: Setting<PathBuf>,
+    /// Extra speller roots, highest precedence first.
+    pub(crate) speller_roots: Vec<Setting<PathBuf>>,
+    pub(crate) mso_dll_32: Setting<Option<String>>,
+    pub(crate) mso_dll_64: Setting<Option<String>>,
+    pub(crate) proof_tool_path: Setting<String>,
+    pub(crate) user_settings_name: Setting<String>,
+    pub(crate) log_dir: Setting<PathBuf>,
+    pub(crate) libreoffice_extension_id: Setting<String>,
+    /// The speller formats the DivvunSpell engine can load. Only ZHFST unless
+    /// set, as spelli cannot tell whether the installed engine reads BHFST.
+    pub(crate) speller_formats: Setting<Vec<Format>>,
+    pub(crate) tombstone_retention_days: Setting<u64>,
+}
+
+/// Where spelli looks for spelli.toml when `--config` is not given.
+pub(crate) fn default_path(scope: Scope) -> PathBuf {
+    let dir = match scope {
+        Scope::Machine => pathos::system::app_data_dir("WinDivvun"),
+        Scope::User => pathos::user::app_data_dir("WinDivvun").unwrap(),
+    };
+    dir.join(CONFIG_FILE)
+}
+
+impl Config {
+    pub(crate) fn defaults(scope: Scope) -> Config {
+        let log_dir = match scope {
+            Scope::Machine => pathos::system::app_log_dir("WinDivvun"),
+            Scope::User => pathos::user::app_log_dir("WinDivvun").unwrap(),
+        };
+
+        Config {
+            file: None,
+            speller_dir: Setting::default(scope.speller_dir()),
+            speller_roots: vec![],
+            mso_dll_32: Setting::default(None),
+            mso_dll_64: Setting::default(None),
+            proof_tool_path: Setting::default(reg::BASE_PROOF_TOOL_PATH.to_string()),
+            user_settings_name: Setting::default(reg::USER_SETTINGS_NAME.to_string()),
+            log_dir: Setting::default(log_dir),
+            libreoffice_extension_id: Setting::default(
+                crate::libreoffice::EXTENSION_ID.to_string(),
+            ),
+            speller_formats: Setting::default(vec![Format::Zhfst]),
+            tombstone_retention_days: Setting::default(
+                crate::refresh::DEFAULT_TOMBSTONE_RETENTION_DAYS,
+            ),
+        }
+    }
+
+    /// Reads `path`, or spelli.toml in its default location if it exists, and
+    /// applies the environment and `overrides` on top.
+    pub(crate) fn load(
+        scope: Scope,
+        path: Option<&Path>,
+        overrides: &Overrides,
+    ) -> Result<Config, Error> {
+        let mut config = Config::defaults(scope);
+
+        let path = match path {
+            Some(path) => Some(path.to_path_buf()),
+            None => Some(default_path(scope)).filter(|x| x.exists()),
+        };
+        let file = match &path {
+            Some(path) => {
+                let text = std::fs::read_to_string(path).map_err(|e| Error::Io(path.clone(), e))?;
+                toml::from_str(&text).map_err(|e| Error::Parse(path.clone(), e))?
+            }
+            None => ConfigFile::default(),
+        };
+        config.file = path.clone();
+
+        let source = Source::File(path.unwrap_or_default());
+        let file_roots = file
+            .speller_roots
+            .into_iter()
+            .map(|value| Setting {
+                value,
+                source: source.clone(),
+            })
+            .collect::<Vec<_>>();
+        config.speller_dir.set(file.speller_dir, &source);
+        config.mso_dll_32.set(file.mso_dll_32.map(Some), &source);
+        config.mso_dll_64.set(file.mso_dll_64.map(Some), &source);
+        config.proof_tool_path.set(file.proof_tool_path, &source);
+        config
+            .user_settings_name
+            .set(file.user_settings_name, &source);
+        config.log_dir.set(file.log_dir, &source);
+        config
+            .libreoffice_extension_id
+            .set(file.libreoffice_extension_id, &source);
+        config.speller_formats.set(file.speller_formats, &source);
+        config
+            .tombstone_retention_days
+            .set(file.refresh.tombstone_retention_days, &source);
+
+        let env_roots = std::env::var_os(SPELLER_DIRS_ENV)
+            .map(|x| std::env::split_paths(&x).collect::<Vec<_>>())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|x| !x.as_os_str().is_empty())
+            .map(|value| Setting {
+                value,
+                source: Source::Environment(SPELLER_DIRS_ENV),
+            });
+
+        let cli = Source::CommandLine;
+        let cli_roots = overrides.speller_roots.iter().map(|value| Setting {
+            value: value.clone(),
+            source: cli.clone(),
+        });
+        config.speller_roots = cli_roots.chain(env_roots).chain(file_roots).collect();
+        config
+            .mso_dll_32
+            .set(overrides.mso_dll_32.clone().map(Some), &cli);
+        config
+            .mso_dll_64
+            .set(overrides.mso_dll_64.clone().map(Some), &cli);
+        config
+            .tombstone_retention_days
+            .set(overrides.tombstone_retention_days, &cli);
+
+        Ok(config)
+    }
+
+    /// Fills in the DivvunSpell MSO DLLs that were not configured from the
+    /// WinDivvun installation on this machine.
+    pub(crate) fn detect_mso_dlls(&mut self, registry: &dyn RegistryBackend) {
+        let dlls = MsoDlls::resolve(
+            registry,
+            self.mso_dll_32.value.as_deref(),
+            self.mso_dll_64.value.as_deref(),
+        );
+
+        if self.mso_dll_32.value.is_none() {
+            self.mso_dll_32
+                .set(Some(Some(dlls.dll32)), &Source::Detected);
+        }
+        if self.mso_dll_64.value.is_none() {
+            self.mso_dll_64
+                .set(Some(Some(dlls.dll64)), &Source::Detected);
+        }
+    }
+
+    /// Every speller root to scan, highest precedence first. When two roots
+    /// provide the same tag, the earlier root wins.
+    pub(crate) fn speller_roots(&self) -> Vec<PathBuf> {
+        let mut out: Vec<PathBuf> = vec![];
+        for root in self
+            .speller_roots
+            .iter()
+            .chain(std::iter::once(&self.speller_dir))
+        {
+            if !out.contains(&root.value) {
+                out.push(root.value.clone());
+            }
+        }
+        out
+    }
+
+    pub(crate) fn refresh_options(&self) -> RefreshOptions {
+        let mut options = RefreshOptions {
+            tombstone_retention: Duration::from_secs(self.tombstone_retention_days.value * 86400),
+            proof_tool_path: self.proof_tool_path.value.clone(),
+            user_settings_name: self.user_settings_name.value.clone(),
+            libreoffice_extension_id: self.libreoffice_extension_id.value.clone(),
+            speller_formats: self.speller_formats.value.clone(),
+            ..Default::default()
+        };
+        if let Some(dll32) = &self.mso_dll_32.value {
+            options.mso_dlls.dll32 = dll32.clone();
+        }
+        if let Some(dll64) = &self.mso_dll_64.value {
+            options.mso_dlls.dll64 = dll64.clone();
+        }
+        options
+    }
+}
+
+impl Display for Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn line(
+            f: &mut std::fmt::Formatter<'_>,
+            name: &str,
+            value: &dyn Display,
+            source: &Source,
+        ) -> std::fmt::Result {
+            writeln!(f, "{:<34} {}  ({})", name, value, source)
+        }
+
+        fn or_unset(value: &Option<String>) -> &str {
+            value.as_deref().unwrap_or("<unset>")
+        }
+
+        match &self.file {
+            Some(path) => writeln!(f, "Configuration file: {}", path.display())?,
+            None => writeln!(f, "Configuration file: none")?,
+        }
+
+        for (i, root) in self.speller_roots.iter().enumerate() {
+            let name = format!("speller_roots[{}]", i);
+            line(f, &name, &root.value.display(), &root.source)?;
+        }
+        let s = &self.speller_dir;
+        line(f, "speller_dir", &s.value.display(), &s.source)?;
+        let s = &self.mso_dll_32;
+        line(f, "mso_dll_32", &or_unset(&s.value), &s.source)?;
+        let s = &self.mso_dll_64;
+        line(f, "mso_dll_64", &or_unset(&s.value), &s.source)?;
+        let s = &self.proof_tool_path;
+        line(f, "proof_tool_path", &s.value, &s.source)?;
+        let s = &self.user_settings_name;
+        line(f, "user_settings_name", &s.value, &s.source)?;
+        let s = &self.log_dir;
+        line(f, "log_dir", &s.value.display(), &s.source)?;
+        let s = &self.libreoffice_extension_id;
+        line(f, "libreoffice_extension_id", &s.value, &s.source)?;
+        let s = &self.speller_formats;
+        let formats = s
+            .value
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        line(f, "speller_formats", &formats, &s.source)?;
+        let s = &self.tombstone_retention_days;
+        line(f, "refresh.tombstone_retention_days", &s.value, &s.source)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn load(name: &str, text: &str) -> Config {
+        let path = testing::temp_dir(name).join(CONFIG_FILE);
+        std::fs::write(&path, text).unwrap();
+        let config = Config::load(Scope::Machine, Some(&path), &Overrides::default()).unwrap();
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        config
+    }
+
+    #[test]
+    fn bhfst_is_only_loaded_when_configured() {
+        let config = load("formats-default", "");
+        assert_eq!(
+            config.refresh_options().speller_formats,
+            vec![Format::Zhfst]
+        );
+
+        let config = load(
+            "formats-bhfst",
+            "speller_formats = [\"bhfst\", \"zhfst\"]\n",
+        );
+        assert_eq!(
+            config.refresh_options().speller_formats,
+            vec![Format::Bhfst, Format::Zhfst]
+        );
+    }
+
+    #[test]
+    fn the_command_line_wins_over_the_environment_and_the_file() {
+        let text = "speller_roots = ['file']\nmso_dll_32 = 'file.dll'\nmso_dll_64 = 'file.dll'\n";
+        std::env::set_var(SPELLER_DIRS_ENV, "env");
+        let overrides = Overrides {
+            speller_roots: vec![PathBuf::from("cli")],
+            mso_dll_64: Some("cli.dll".to_string()),
+            ..Default::default()
+        };
+        let path = testing::temp_dir("precedence").join(CONFIG_FILE);
+        std::fs::write(&path, text).unwrap();
+        let config = Config::load(Scope::Machine, Some(&path), &overrides).unwrap();
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        std::env::remove_var(SPELLER_DIRS_ENV);
+
+        let roots = config.speller_roots();
+        assert_eq!(&roots[..3], ["cli", "env", "file"].map(PathBuf::from));
+        assert_eq!(roots[3], Scope::Machine.speller_dir());
+        assert_eq!(
+            config.speller_roots[1].source,
+            Source::Environment(SPELLER_DIRS_ENV)
+        );
+        assert_eq!(config.speller_roots[2].source, Source::File(path));
+
+        assert_eq!(config.mso_dll_32.value.as_deref(), Some("file.dll"));
+        assert_eq!(config.mso_dll_64.value.as_deref(), Some("cli.dll"));
+        assert_eq!(config.mso_dll_64.source, Source::CommandLine);
+    }
+}
@@ -1,16 +1,112 @@
-// use crate::DeregisterArgs;
-// use unic_langid::LanguageIdentifier;
+use std::path::PathBuf;
 
-// pub(crate) fn deregister(args: DeregisterArgs) -> Result<(), crate::register::Error> {
-//     let lang_id: LanguageIdentifier = args.tag.parse()?;
-//     log::info!("Deregistering speller for '{}'...", &lang_id);
+use unic_langid::LanguageIdentifier;
 
-//     let keys = crate::register::derive_lang_id_keys(lang_id)?;
-//     crate::reg::deregister_langs(&keys)?;
+use crate::backend::{RegistryBackend, Value};
+use crate::clock::Clock;
+use crate::filesystem::FileSystem;
+use crate::plan::Plan;
+use crate::refresh::RefreshOptions;
+use crate::reg::Scope;
+use crate::register::Error;
 
-//     crate::refresh::refresh();
+/// Deregisters `tag` and everything derived from it. A manual registration is
+/// simply removed; a tag a speller package provides is recorded as deregistered
+/// so later refreshes do not bring it back until it is registered again.
+pub(crate) fn deregister(
+    registry: &dyn RegistryBackend,
+    fs: &dyn FileSystem,
+    clock: &dyn Clock,
+    speller_roots: &[PathBuf],
+    scope: Scope,
+    options: &RefreshOptions,
+    tag: &str,
+) -> Result<(), Error> {
+    let lang_id: LanguageIdentifier = tag.parse()?;
+    log::info!("Deregistering speller for '{}'...", &lang_id);
 
-//     log::info!("Deregistration complete!");
+    let keys = crate::register::derive_lang_id_keys(lang_id.clone())?;
+    let provided = crate::refresh::registrations(fs, speller_roots, &options.speller_formats)?
+        .iter()
+        .any(|x| x.tags.iter().any(|tag| keys.contains(tag)));
 
-//     Ok(())
-// }
+    let tag = lang_id.to_string();
+    let key = crate::reg::manual_spellers_key(scope);
+    let mut plan = Plan::new();
+    let mut manual = crate::reg::manual_registrations(registry, scope)?;
+
+    if provided {
+        log::info!("Storing manual deregistration of '{}'", &tag);
+        plan.set_value(&key, &tag, Value::None);
+        manual.insert(tag, None);
+    } else if manual.remove(&tag).is_some() {
+        log::info!("Removing manual registration of '{}'", &tag);
+        plan.delete_value(&key, &tag);
+    } else {
+        log::warn!("'{}' is not registered", &tag);
+        return Ok(());
+    }
+
+    crate::refresh::plan_with(
+        registry,
+        fs,
+        clock,
+        speller_roots,
+        scope,
+        &manual,
+        options,
+        &mut plan,
+    )?;
+    plan.apply(registry)?;
+
+    log::info!("Deregistration complete!");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, FixedClock};
+
+    #[test]
+    fn deregistering_a_package_tag_stores_none() {
+        let (registry, mut fs) = testing::machine();
+        testing::add_package(
+            &mut fs,
+            "se",
+            "[spellers]\nse = \"se.zhfst\"\n",
+            &["se.zhfst"],
+        );
+        let options = RefreshOptions::default();
+        let clock = FixedClock::at(1000);
+        let roots = testing::roots();
+
+        crate::refresh::plan(&registry, &fs, &clock, &roots, Scope::Machine, &options)
+            .unwrap()
+            .apply(&registry)
+            .unwrap();
+        deregister(
+            &registry,
+            &fs,
+            &clock,
+            &roots,
+            Scope::Machine,
+            &options,
+            "se",
+        )
+        .unwrap();
+
+        let manual = crate::reg::manual_registrations(&registry, Scope::Machine).unwrap();
+        assert_eq!(manual.get("se"), Some(&None));
+
+        // A later refresh does not bring it back
+        clock.set(2000);
+        crate::refresh::plan(&registry, &fs, &clock, &roots, Scope::Machine, &options)
+            .unwrap()
+            .apply(&registry)
+            .unwrap();
+        let langs = crate::reg::Langs::new(&registry, Scope::Machine).unwrap();
+        assert!(!langs.create.contains_key("se"));
+    }
+}
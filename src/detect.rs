@@ -0,0 +1,21 @@
+use crate::backend::RegistryBackend;
+use crate::filesystem::FileSystem;
+
+pub(crate) fn detect(registry: &dyn RegistryBackend, fs: &dyn FileSystem) {
+    println!("Microsoft Office installations:");
+
+    let offices = crate::refresh::detect_ms_office(registry);
+    if offices.is_empty() {
+        println!("  - None found.");
+    }
+    for office in offices {
+        println!(" - {}", office);
+    }
+
+    println!("LibreOffice:");
+
+    match crate::libreoffice::find_unopkg(registry, fs) {
+        Some(unopkg) => println!(" - unopkg at {}", unopkg.display()),
+        None => println!("  - None found."),
+    }
+}
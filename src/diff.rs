@@ -0,0 +1,601 @@
+use std::{collections::BTreeMap, fmt::Display, path::PathBuf};
+
+use serde::Serialize;
+
+use crate::backend::{Hive, KeyPath, RegistryBackend, Value, View};
+use crate::filesystem::FileSystem;
+use crate::host::Host;
+use crate::mso::{self, MsoDlls};
+use crate::package::{self, Package};
+use crate::refresh::{self, RefreshOptions};
+use crate::reg::{self, Langs, Scope};
+
+/// One way the registry differs from what a refresh would produce. `scope` is
+/// the Spellers key or the Office User Settings path the difference was found in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(crate) enum Difference {
+    MissingTag {
+        scope: String,
+        tag: String,
+        expected: String,
+    },
+    StaleTag {
+        scope: String,
+        tag: String,
+        actual: String,
+    },
+    WrongPath {
+        scope: String,
+        tag: String,
+        name: String,
+        expected: String,
+        actual: Option<String>,
+    },
+    WrongDll {
+        scope: String,
+        tag: String,
+        name: String,
+        expected: String,
+        actual: Option<String>,
+    },
+    OutdatedCount {
+        scope: String,
+        count: Option<u32>,
+        spellers_updated: u32,
+    },
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn or_none(value: &Option<String>) -> &str {
+            value.as_deref().unwrap_or("<missing>")
+        }
+
+        match self {
+            Difference::MissingTag {
+                scope,
+                tag,
+                expected,
+            } => write!(f, "Missing '{}' -> '{}' in {}", tag, expected, scope),
+            Difference::StaleTag { scope, tag, actual } => {
+                write!(f, "Stale '{}' -> '{}' in {}", tag, actual, scope)
+            }
+            Difference::WrongPath {
+                scope,
+                tag,
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Wrong path for '{}' in {}: {} is '{}', expected '{}'",
+                tag,
+                scope,
+                name,
+                or_none(actual),
+                expected
+            ),
+            Difference::WrongDll {
+                scope,
+                tag,
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Wrong DLL for '{}' in {}: {} is '{}', expected '{}'",
+                tag,
+                scope,
+                name,
+                or_none(actual),
+                expected
+            ),
+            Difference::OutdatedCount {
+                scope,
+                count,
+                spellers_updated,
+            } => match count {
+                Some(count) => write!(
+                    f,
+                    "Count in {} is {}, older than the Spellers key ({})",
+                    scope, count, spellers_updated
+                ),
+                None => write!(f, "Count in {} is missing", scope),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub(crate) struct Report {
+    /// The speller packages found in the speller roots.
+    pub(crate) packages: Vec<Package>,
+    pub(crate) differences: Vec<Difference>,
+}
+
+impl Report {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.differences.is_empty()
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !self.packages.is_empty() {
+            writeln!(f, "Speller packages:")?;
+            for package in self.packages.iter() {
+                writeln!(f, " - {}", package)?;
+            }
+        }
+
+        if self.differences.is_empty() {
+            return writeln!(f, "No differences found.");
+        }
+
+        writeln!(f, "{} differences found:", self.differences.len())?;
+        for difference in self.differences.iter() {
+            writeln!(f, " - {}", difference)?;
+        }
+        Ok(())
+    }
+}
+
+fn string_value(registry: &dyn RegistryBackend, key: &KeyPath, name: &str) -> Option<String> {
+    match registry.value(key, name) {
+        Ok(Some(Value::String(s))) => Some(s),
+        Ok(Some(other)) => Some(other.to_string()),
+        _ => None,
+    }
+}
+
+fn same_path(a: &str, b: &str) -> bool {
+    a.to_lowercase() == b.to_lowercase()
+}
+
+fn subkeys_or_empty(
+    registry: &dyn RegistryBackend,
+    key: &KeyPath,
+) -> Result<Vec<String>, reg::Error> {
+    match registry.subkeys(key) {
+        Err(reg::Error::NotFound(_)) => Ok(vec![]),
+        result => result,
+    }
+}
+
+/// Tags keyed by their lowercase form, since the registry ignores case.
+type Tags = BTreeMap<String, (String, String)>;
+
+fn to_tags<'a>(iter: impl Iterator<Item = (&'a String, &'a String)>) -> Tags {
+    iter.map(|(tag, path)| (tag.to_lowercase(), (tag.clone(), path.clone())))
+        .collect()
+}
+
+/// Compares the tags of the Spellers key, or of a host's list, at `scope`.
+fn diff_spellers(scope: &str, desired: &Tags, actual: &Tags, out: &mut Vec<Difference>) {
+    for (id, (tag, expected)) in desired.iter() {
+        match actual.get(id) {
+            None => out.push(Difference::MissingTag {
+                scope: scope.to_string(),
+                tag: tag.clone(),
+                expected: expected.clone(),
+            }),
+            Some((_, path)) if !same_path(path, expected) => out.push(Difference::WrongPath {
+                scope: scope.to_string(),
+                tag: tag.clone(),
+                name: tag.clone(),
+                expected: expected.clone(),
+                actual: Some(path.clone()),
+            }),
+            Some(_) => {}
+        }
+    }
+
+    for (id, (tag, path)) in actual.iter() {
+        if !desired.contains_key(id) {
+            out.push(Difference::StaleTag {
+                scope: scope.to_string(),
+                tag: tag.clone(),
+                actual: path.clone(),
+            });
+        }
+    }
+}
+
+/// Checks that a Create record or per-user override points at the right speller and DLLs.
+fn diff_record(
+    registry: &dyn RegistryBackend,
+    scope: &str,
+    tag: &str,
+    expected: &str,
+    key: &KeyPath,
+    dlls: &MsoDlls,
+    out: &mut Vec<Difference>,
+) -> Result<(), reg::Error> {
+    for name in ["LEX", "LEX64"].iter() {
+        let actual = string_value(registry, key, name);
+        if !actual
+            .as_deref()
+            .map(|x| same_path(x, expected))
+            .unwrap_or(false)
+        {
+            out.push(Difference::WrongPath {
+                scope: scope.to_string(),
+                tag: tag.to_string(),
+                name: name.to_string(),
+                expected: expected.to_string(),
+                actual,
+            });
+        }
+    }
+
+    let dlls = [("DLL", &dlls.dll32), ("DLL64", &dlls.dll64)];
+    for (name, expected) in dlls.iter() {
+        let actual = string_value(registry, key, name);
+        if !actual
+            .as_deref()
+            .map(|x| same_path(x, expected))
+            .unwrap_or(false)
+        {
+            out.push(Difference::WrongDll {
+                scope: scope.to_string(),
+                tag: tag.to_string(),
+                name: name.to_string(),
+                expected: expected.to_string(),
+                actual,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn diff_user_settings(
+    registry: &dyn RegistryBackend,
+    desired: &Tags,
+    base_path: &str,
+    spellers_updated: Option<u32>,
+    options: &RefreshOptions,
+    out: &mut Vec<Difference>,
+) -> Result<(), reg::Error> {
+    let scope = base_path.to_string();
+    let create_key = reg::create_records_key(base_path, options);
+    let delete_key = reg::delete_records_key(base_path, options);
+
+    let created = subkeys_or_empty(registry, &create_key)?;
+    let deleted = subkeys_or_empty(registry, &delete_key)?;
+    let created_ids = created.iter().map(|x| x.to_lowercase()).collect::<Vec<_>>();
+    let deleted_ids = deleted.iter().map(|x| x.to_lowercase()).collect::<Vec<_>>();
+
+    for (id, (tag, expected)) in desired.iter() {
+        // A Delete record wins over a Create record for the same tag.
+        if !created_ids.contains(id) || deleted_ids.contains(id) {
+            out.push(Difference::MissingTag {
+                scope: scope.clone(),
+                tag: tag.clone(),
+                expected: expected.clone(),
+            });
+            continue;
+        }
+
+        diff_record(
+            registry,
+            &scope,
+            tag,
+            expected,
+            &create_key.join(tag),
+            &options.mso_dlls,
+            out,
+        )?;
+    }
+
+    for tag in created.iter() {
+        let id = tag.to_lowercase();
+        if !desired.contains_key(&id) && !deleted_ids.contains(&id) {
+            out.push(Difference::StaleTag {
+                scope: scope.clone(),
+                tag: tag.clone(),
+                actual: string_value(registry, &create_key.join(tag), "LEX").unwrap_or_default(),
+            });
+        }
+    }
+
+    let base_key = KeyPath::new(Hive::LocalMachine, View::Registry64, base_path);
+    let count = match registry.value(&base_key, "Count")? {
+        Some(Value::U32(x)) => Some(x),
+        _ => None,
+    };
+
+    if let Some(spellers_updated) = spellers_updated {
+        let outdated = count.map(|x| x < spellers_updated).unwrap_or(true);
+        if outdated && registry.key_exists(&base_key)? {
+            out.push(Difference::OutdatedCount {
+                scope,
+                count,
+                spellers_updated,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn diff_user_overrides(
+    registry: &dyn RegistryBackend,
+    desired: &Tags,
+    langs: &Langs,
+    options: &RefreshOptions,
+    out: &mut Vec<Difference>,
+) -> Result<(), reg::Error> {
+    let base = reg::user_overrides_key(options);
+    let scope = base.to_string();
+
+    for (tag, expected) in desired.values() {
+        let key = base.join(tag);
+        if !registry.key_exists(&key)? {
+            out.push(Difference::MissingTag {
+                scope: scope.clone(),
+                tag: tag.clone(),
+                expected: expected.clone(),
+            });
+            continue;
+        }
+
+        diff_record(
+            registry,
+            &scope,
+            tag,
+            expected,
+            &key,
+            &options.mso_dlls,
+            out,
+        )?;
+    }
+
+    // Registered tags Office should not have are stale too
+    for tag in langs.delete.iter().chain(langs.create.keys()) {
+        if desired.contains_key(&tag.to_lowercase()) {
+            continue;
+        }
+
+        let key = base.join(tag);
+        if let Some(dll) = string_value(registry, &key, "DLL64") {
+            if mso::is_divvunspell_mso(&dll) {
+                out.push(Difference::StaleTag {
+                    scope: scope.clone(),
+                    tag: tag.clone(),
+                    actual: string_value(registry, &key, "LEX").unwrap_or_default(),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Compares the registrations the speller.toml files in `speller_roots` call
+/// for against the Spellers key and the Office overrides of `scope`. Read-only.
+pub(crate) fn diff(
+    registry: &dyn RegistryBackend,
+    fs: &dyn FileSystem,
+    speller_roots: &[PathBuf],
+    scope: Scope,
+    options: &RefreshOptions,
+) -> Result<Report, refresh::Error> {
+    // Tags by where they belong: the Spellers key, the Hidden key, and Office
+    let mut shown = BTreeMap::new();
+    let mut hidden = BTreeMap::new();
+    let mut office = BTreeMap::new();
+    let manual = reg::manual_registrations(registry, scope)?;
+    for registration in
+        refresh::desired_registrations(fs, speller_roots, &manual, &options.speller_formats)?
+    {
+        let path = registration.path.to_string_lossy().to_string();
+        let is_shown = Host::SPELLERS_KEY_READERS
+            .iter()
+            .any(|x| registration.is_for(*x));
+        let for_office = registration.is_for(Host::Office);
+        for tag in registration.tags {
+            let id = tag.to_lowercase();
+            let value = (tag, path.clone());
+            for (tags, wanted) in [
+                (&mut shown, is_shown),
+                (&mut hidden, !is_shown),
+                (&mut office, for_office),
+            ] {
+                if wanted {
+                    tags.insert(id.clone(), value.clone());
+                } else {
+                    tags.remove(&id);
+                }
+            }
+        }
+    }
+
+    let langs = Langs::new(registry, scope)?;
+    let mut differences = vec![];
+    diff_spellers(
+        &reg::spellers_key(scope).to_string(),
+        &shown,
+        &to_tags(
+            langs
+                .create
+                .iter()
+                .filter(|(tag, _)| !langs.hidden.contains(*tag)),
+        ),
+        &mut differences,
+    );
+    diff_spellers(
+        &reg::hidden_spellers_key(scope).to_string(),
+        &hidden,
+        &to_tags(
+            langs
+                .create
+                .iter()
+                .filter(|(tag, _)| langs.hidden.contains(*tag)),
+        ),
+        &mut differences,
+    );
+
+    match scope {
+        Scope::Machine => {
+            let spellers_updated = reg::spellers_updated(registry, scope)?;
+            for path in refresh::user_settings_paths(registry, options) {
+                diff_user_settings(
+                    registry,
+                    &office,
+                    &path,
+                    spellers_updated,
+                    options,
+                    &mut differences,
+                )?;
+            }
+        }
+        Scope::User => diff_user_overrides(registry, &office, &langs, options, &mut differences)?,
+    }
+
+    Ok(Report {
+        packages: package::packages(fs, speller_roots),
+        differences,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryRegistry;
+    use crate::filesystem::MemoryFileSystem;
+    use crate::testing::{self, FixedClock};
+
+    const SE: &str = "[spellers]\nse = \"se.zhfst\"\n";
+
+    const BASE_PATH: &str = r"SOFTWARE\Microsoft\Office\16.0\User Settings\WinDivvun";
+
+    /// A machine with one package, refreshed at `scope`, and the path of its speller.
+    fn refreshed(scope: Scope) -> (MemoryRegistry, MemoryFileSystem, String) {
+        let (registry, mut fs) = testing::machine();
+        let dir = testing::add_package(&mut fs, "se", SE, &["se.zhfst"]);
+        let clock = FixedClock::at(1000);
+        refresh::plan(
+            &registry,
+            &fs,
+            &clock,
+            &testing::roots(),
+            scope,
+            &RefreshOptions::default(),
+        )
+        .unwrap()
+        .apply(&registry)
+        .unwrap();
+        let speller = dir.join("se.zhfst").to_string_lossy().to_string();
+        (registry, fs, speller)
+    }
+
+    fn differences(
+        registry: &MemoryRegistry,
+        fs: &MemoryFileSystem,
+        scope: Scope,
+    ) -> Vec<Difference> {
+        diff(
+            registry,
+            fs,
+            &testing::roots(),
+            scope,
+            &RefreshOptions::default(),
+        )
+        .unwrap()
+        .differences
+    }
+
+    #[test]
+    fn a_refreshed_machine_has_no_differences() {
+        for scope in [Scope::Machine, Scope::User].iter() {
+            let (registry, fs, _) = refreshed(*scope);
+            assert_eq!(differences(&registry, &fs, *scope), vec![]);
+        }
+    }
+
+    #[test]
+    fn missing_tags_are_reported() {
+        let (registry, fs, speller) = refreshed(Scope::Machine);
+        let spellers = reg::spellers_key(Scope::Machine);
+        registry.delete_value(&spellers, "se").unwrap();
+
+        assert!(
+            differences(&registry, &fs, Scope::Machine).contains(&Difference::MissingTag {
+                scope: spellers.to_string(),
+                tag: "se".to_string(),
+                expected: speller,
+            })
+        );
+    }
+
+    #[test]
+    fn stale_tags_are_reported() {
+        let (registry, fs, _) = refreshed(Scope::Machine);
+        let spellers = reg::spellers_key(Scope::Machine);
+        let stale = r"C:\Spellers\sma.zhfst".to_string();
+        registry
+            .set_value(&spellers, "sma", &Value::String(stale.clone()))
+            .unwrap();
+
+        assert_eq!(
+            differences(&registry, &fs, Scope::Machine),
+            vec![Difference::StaleTag {
+                scope: spellers.to_string(),
+                tag: "sma".to_string(),
+                actual: stale,
+            }]
+        );
+    }
+
+    #[test]
+    fn a_count_older_than_the_spellers_key_is_reported() {
+        let (registry, fs, _) = refreshed(Scope::Machine);
+        let base = KeyPath::new(Hive::LocalMachine, View::Registry64, BASE_PATH);
+        registry
+            .set_value(&base, "Count", &Value::U32(999))
+            .unwrap();
+
+        assert_eq!(
+            differences(&registry, &fs, Scope::Machine),
+            vec![Difference::OutdatedCount {
+                scope: BASE_PATH.to_string(),
+                count: Some(999),
+                spellers_updated: 1000,
+            }]
+        );
+    }
+
+    #[test]
+    fn user_overrides_pointing_elsewhere_are_reported() {
+        let (registry, fs, speller) = refreshed(Scope::User);
+        let options = RefreshOptions::default();
+        let overrides = reg::user_overrides_key(&options);
+        let other = r"C:\Spellers\se.zhfst".to_string();
+        registry
+            .set_value(&overrides.join("se"), "LEX", &Value::String(other.clone()))
+            .unwrap();
+
+        assert_eq!(
+            differences(&registry, &fs, Scope::User),
+            vec![Difference::WrongPath {
+                scope: overrides.to_string(),
+                tag: "se".to_string(),
+                name: "LEX".to_string(),
+                expected: speller.clone(),
+                actual: Some(other),
+            }]
+        );
+
+        registry.delete_key(&overrides.join("se")).unwrap();
+        assert_eq!(
+            differences(&registry, &fs, Scope::User),
+            vec![Difference::MissingTag {
+                scope: overrides.to_string(),
+                tag: "se".to_string(),
+                expected: speller,
+            }]
+        );
+    }
+}
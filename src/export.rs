@@ -0,0 +1,372 @@
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+use crate::backend::{Hive, KeyPath, MemoryRegistry, RegistryBackend, Value, View};
+use crate::clock::Clock;
+use crate::filesystem::FileSystem;
+use crate::plan::{Plan, Step};
+use crate::refresh::{self, RefreshOptions};
+use crate::reg::{self, Scope};
+
+const HEADER: &str = "Windows Registry Editor Version 5.00";
+
+/// Escapes a string for use inside double quotes in a .reg file.
+fn escape(s: &str) -> String {
+    s.replace('\\', r"\\").replace('"', r#"\""#)
+}
+
+/// Renders bytes as a `hex(n):` value, wrapping long lines like regedit does.
+fn hex(kind: u32, bytes: &[u8]) -> String {
+    let mut out = format!("hex({:x}):", kind);
+    let mut line_len = out.len();
+
+    for (i, byte) in bytes.iter().enumerate() {
+        let item = if i + 1 == bytes.len() {
+            format!("{:02x}", byte)
+        } else {
+            format!("{:02x},", byte)
+        };
+
+        if line_len + item.len() > 76 {
+            out.push_str("\\\r\n  ");
+            line_len = 2;
+        }
+
+        line_len += item.len();
+        out.push_str(&item);
+    }
+
+    out
+}
+
+fn render_value(value: &Value) -> String {
+    match value {
+        Value::None => hex(0, &[]),
+        // Plain string syntax has no way to represent control characters, so fall
+        // back to the raw UTF-16LE bytes (including the terminating nul) for those.
+        Value::String(s) if s.chars().any(char::is_control) => {
+            let bytes = s
+                .encode_utf16()
+                .chain(std::iter::once(0))
+                .flat_map(|x| x.to_le_bytes().to_vec())
+                .collect::<Vec<_>>();
+            hex(1, &bytes)
+        }
+        Value::String(s) => format!("\"{}\"", escape(s)),
+        Value::U32(x) => format!("dword:{:08x}", x),
+    }
+}
+
+fn render_name(name: &str) -> String {
+    if name.is_empty() {
+        "@".to_string()
+    } else {
+        format!("\"{}\"", escape(name))
+    }
+}
+
+fn render_key(key: &KeyPath) -> String {
+    format!("{}\\{}", key.hive, key.segments().join("\\"))
+}
+
+/// Plans everything a refresh would write on a machine that has nothing
+/// registered yet, so the export holds the full state rather than the changes
+/// against this machine. Manual registrations are carried over, and tags this
+/// machine has tombstoned or would deregister are exported as tombstones, so
+/// they get Delete records wherever the export is imported.
+pub(crate) fn plan(
+    registry: &dyn RegistryBackend,
+    fs: &dyn FileSystem,
+    clock: &dyn Clock,
+    speller_roots: &[PathBuf],
+    scope: Scope,
+    options: &RefreshOptions,
+) -> Result<Plan, refresh::Error> {
+    let manual = reg::manual_registrations(registry, scope)?;
+
+    // Office detection takes this key to mean a 64-bit machine, where both the
+    // native and the 32-bit User Settings paths are written
+    let empty = MemoryRegistry::new();
+    empty.create_key(&KeyPath::new(
+        Hive::LocalMachine,
+        View::Default,
+        r"SOFTWARE\Wow6432Node",
+    ))?;
+
+    let mut plan = Plan::new();
+    for (tag, path) in manual.iter() {
+        let value = match path {
+            Some(path) => Value::String(path.clone()),
+            None => Value::None,
+        };
+        plan.set_value(&reg::manual_spellers_key(scope), tag, value);
+    }
+
+    let desired =
+        refresh::desired_registrations(fs, speller_roots, &manual, &options.speller_formats)?
+            .into_iter()
+            .flat_map(|x| x.tags)
+            .map(|x| x.to_lowercase())
+            .collect::<BTreeSet<_>>();
+    let langs = reg::Langs::new(registry, scope)?;
+    let known = langs.delete.iter().chain(langs.create.keys());
+    for tag in known.filter(|x| !desired.contains(&x.to_lowercase())) {
+        empty.set_value(&reg::spellers_key(scope), tag, &Value::None)?;
+        plan.set_value(&reg::spellers_key(scope), tag, Value::None);
+        if let Some(written) = langs.tombstones.get(tag) {
+            empty.set_value(&reg::tombstones_key(scope), tag, &Value::U32(*written))?;
+            plan.set_value(&reg::tombstones_key(scope), tag, Value::U32(*written));
+        }
+    }
+
+    refresh::plan_with(
+        &empty,
+        fs,
+        clock,
+        speller_roots,
+        scope,
+        &manual,
+        options,
+        &mut plan,
+    )?;
+    Ok(plan)
+}
+
+/// Renders the registry steps of a plan as a .reg file. Steps that do not touch
+/// the registry cannot be expressed and are left as comments.
+pub(crate) fn render(plan: &Plan) -> String {
+    let mut lines = vec![HEADER.to_string()];
+    let mut current: Option<&KeyPath> = None;
+
+    for step in plan.steps.iter() {
+        match step {
+            Step::CreateKey { key } => {
+                lines.push(String::new());
+                lines.push(format!("[{}]", render_key(key)));
+                current = Some(key);
+            }
+            Step::DeleteKey { key } => {
+                lines.push(String::new());
+                lines.push(format!("[-{}]", render_key(key)));
+                current = None;
+            }
+            Step::SetValue { key, name, value } => {
+                if current.map(render_key) != Some(render_key(key)) {
+                    lines.push(String::new());
+                    lines.push(format!("[{}]", render_key(key)));
+                    current = Some(key);
+                }
+                lines.push(format!("{}={}", render_name(name), render_value(value)));
+            }
+            Step::SetCounter { key, name, value } => {
+                if current.map(render_key) != Some(render_key(key)) {
+                    lines.push(String::new());
+                    lines.push(format!("[{}]", render_key(key)));
+                    current = Some(key);
+                }
+                // A .reg file cannot read the stored value, so this is only
+                // correct if nothing has set it higher since the export
+                lines.push(format!(
+                    "; {} must be higher than the stored value for Office to apply the changes",
+                    name
+                ));
+                lines.push(format!(
+                    "{}={}",
+                    render_name(name),
+                    render_value(&Value::U32(*value))
+                ));
+            }
+            Step::DeleteValue { key, name } => {
+                if current.map(render_key) != Some(render_key(key)) {
+                    lines.push(String::new());
+                    lines.push(format!("[{}]", render_key(key)));
+                    current = Some(key);
+                }
+
+                lines.push(format!("{}=-", render_name(name)));
+            }
+            other => {
+                log::warn!("Not exported: {}", other);
+                lines.push(String::new());
+                lines.push(format!("; Not exported: {}", other));
+                current = None;
+            }
+        }
+    }
+
+    lines.push(String::new());
+    lines.join("\r\n")
+}
+
+/// Writes a .reg file as UTF-16LE with a byte order mark, which is what regedit produces.
+pub(crate) fn write(plan: &Plan, path: &Path) -> Result<(), std::io::Error> {
+    let text = render(plan);
+    let bytes = std::iter::once(0xfeffu16)
+        .chain(text.encode_utf16())
+        .flat_map(|x| x.to_le_bytes().to_vec())
+        .collect::<Vec<_>>();
+
+    std::fs::write(path, bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, FixedClock};
+
+    fn render_steps(steps: Vec<Step>) -> Vec<String> {
+        render(&Plan { steps })
+            .split("\r\n")
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn strings_are_escaped() {
+        let key = KeyPath::new(Hive::LocalMachine, View::Registry64, r"SOFTWARE\A");
+        let lines = render_steps(vec![Step::SetValue {
+            key,
+            name: r#"a"b\c"#.to_string(),
+            value: Value::String(r#"C:\x "y""#.to_string()),
+        }]);
+
+        assert_eq!(
+            lines,
+            vec![
+                HEADER,
+                "",
+                r"[HKEY_LOCAL_MACHINE\SOFTWARE\A]",
+                r#""a\"b\\c"="C:\\x \"y\"""#,
+                "",
+            ]
+        );
+    }
+
+    #[test]
+    fn control_characters_are_written_as_hex() {
+        assert_eq!(
+            render_value(&Value::String("a\nb".to_string())),
+            "hex(1):61,00,0a,00,62,00,00,00"
+        );
+    }
+
+    #[test]
+    fn default_values_and_deletions() {
+        let key = KeyPath::new(Hive::CurrentUser, View::Default, r"SOFTWARE\A");
+        let lines = render_steps(vec![
+            Step::SetValue {
+                key: key.clone(),
+                name: String::new(),
+                value: Value::U32(0x1f),
+            },
+            Step::DeleteValue {
+                key: key.clone(),
+                name: "b".to_string(),
+            },
+            Step::DeleteKey { key },
+        ]);
+
+        assert_eq!(
+            lines,
+            vec![
+                HEADER,
+                "",
+                r"[HKEY_CURRENT_USER\SOFTWARE\A]",
+                "@=dword:0000001f",
+                r#""b"=-"#,
+                "",
+                r"[-HKEY_CURRENT_USER\SOFTWARE\A]",
+                "",
+            ]
+        );
+    }
+
+    #[test]
+    fn the_32_bit_view_is_redirected() {
+        let key = KeyPath::new(Hive::LocalMachine, View::Registry32, r"SOFTWARE\A");
+        assert_eq!(
+            render_key(&key),
+            r"HKEY_LOCAL_MACHINE\SOFTWARE\Wow6432Node\A"
+        );
+    }
+
+    #[test]
+    fn exports_hold_the_full_state_after_a_refresh() {
+        let (registry, mut fs) = testing::machine();
+        testing::add_package(
+            &mut fs,
+            "se",
+            "[spellers]\nse = \"se.zhfst\"\n",
+            &["se.zhfst"],
+        );
+        let options = RefreshOptions::default();
+        let clock = FixedClock::at(1000);
+        let roots = testing::roots();
+
+        refresh::plan(&registry, &fs, &clock, &roots, Scope::Machine, &options)
+            .unwrap()
+            .apply(&registry)
+            .unwrap();
+
+        let text = render(&plan(&registry, &fs, &clock, &roots, Scope::Machine, &options).unwrap());
+        let lines = text.split("\r\n").collect::<Vec<_>>();
+        for path in refresh::all_user_settings_paths(&options) {
+            let key = format!(
+                r"[HKEY_LOCAL_MACHINE\{}\Create\{}\se]",
+                path, options.proof_tool_path
+            );
+            assert!(lines.contains(&key.as_str()), "{} is missing", key);
+        }
+        assert!(lines.contains(&r"[HKEY_LOCAL_MACHINE\SOFTWARE\WinDivvun\Spellers]"));
+    }
+
+    #[test]
+    fn deregistered_and_stale_tags_are_exported_as_tombstones() {
+        let (registry, mut fs) = testing::machine();
+        let se = testing::add_package(
+            &mut fs,
+            "se",
+            "[spellers]\nse = \"se.zhfst\"\n",
+            &["se.zhfst"],
+        );
+        let options = RefreshOptions::default();
+        let clock = FixedClock::at(1000);
+        let roots = testing::roots();
+        refresh::plan(&registry, &fs, &clock, &roots, Scope::Machine, &options)
+            .unwrap()
+            .apply(&registry)
+            .unwrap();
+
+        // se is deregistered by a refresh, fi goes stale without one
+        let (_, fs) = testing::machine();
+        refresh::plan(&registry, &fs, &clock, &roots, Scope::Machine, &options)
+            .unwrap()
+            .apply(&registry)
+            .unwrap();
+        let fi = se.join("fi.zhfst").to_string_lossy().to_string();
+        registry
+            .set_value(&reg::spellers_key(Scope::Machine), "fi", &Value::String(fi))
+            .unwrap();
+
+        let text = render(&plan(&registry, &fs, &clock, &roots, Scope::Machine, &options).unwrap());
+        let lines = text.split("\r\n").collect::<Vec<_>>();
+        assert!(lines.contains(&r#""se"=hex(0):"#));
+        assert!(lines.contains(&r#""fi"=hex(0):"#));
+        for path in refresh::all_user_settings_paths(&options) {
+            for tag in ["se", "fi"].iter() {
+                let create = format!(
+                    r"[-HKEY_LOCAL_MACHINE\{}\Create\{}\{}]",
+                    path, options.proof_tool_path, tag
+                );
+                let delete = format!(
+                    r"[HKEY_LOCAL_MACHINE\{}\Delete\{}\{}]",
+                    path, options.proof_tool_path, tag
+                );
+                assert!(lines.contains(&create.as_str()), "{} is missing", create);
+                assert!(lines.contains(&delete.as_str()), "{} is missing", delete);
+            }
+        }
+    }
+}
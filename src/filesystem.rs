@@ -0,0 +1,253 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// The filesystem reads spelli performs while planning a refresh.
+pub(crate) trait FileSystem {
+    /// Lists the full paths of all entries in a directory.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String>;
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// The names of the entries in a zip archive, such as a ZHFST speller.
+    fn archive_entries(&self, path: &Path) -> io::Result<Vec<String>>;
+
+    /// The SHA-256 of a file, in lowercase hex.
+    fn sha256(&self, path: &Path) -> io::Result<String>;
+
+    /// Up to the first `len` bytes of a file, to recognise its format.
+    fn header(&self, path: &Path, len: usize) -> io::Result<Vec<u8>>;
+
+    fn is_dir(&self, path: &Path) -> bool;
+
+    fn exists(&self, path: &Path) -> bool;
+}
+
+/// The filesystem of the machine spelli is running on.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct RealFileSystem;
+
+impl FileSystem for RealFileSystem {
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        std::fs::read_dir(path)?
+            .map(|x| x.map(|entry| entry.path()))
+            .collect()
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        std::fs::read_to_string(path)
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        std::fs::read(path)
+    }
+
+    fn archive_entries(&self, path: &Path) -> io::Result<Vec<String>> {
+        let archive = zip::ZipArchive::new(std::fs::File::open(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(archive.file_names().map(|x| x.to_string()).collect())
+    }
+
+    fn sha256(&self, path: &Path) -> io::Result<String> {
+        let mut hasher = Sha256::new();
+        io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+        Ok(hasher
+            .finalize()
+            .iter()
+            .map(|x| format!("{:02x}", x))
+            .collect())
+    }
+
+    fn header(&self, path: &Path, len: usize) -> io::Result<Vec<u8>> {
+        let mut out = vec![];
+        std::fs::File::open(path)?
+            .take(len as u64)
+            .read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Entry {
+    Dir {
+        path: PathBuf,
+    },
+    File {
+        path: PathBuf,
+        contents: Option<String>,
+        /// The entry names, for files that are zip archives.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        archive: Option<Vec<String>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sha256: Option<String>,
+        /// The first bytes, for speller files recorded without contents.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        header: Option<Vec<u8>>,
+    },
+}
+
+/// A filesystem made of recorded entries. Paths are compared the way Windows
+/// does, so a capture taken on Windows can be read back anywhere. Files
+/// recorded without contents exist but cannot be read.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct MemoryFileSystem {
+    entries: BTreeMap<String, Entry>,
+}
+
+fn normalize(path: &Path) -> String {
+    path.to_string_lossy()
+        .split(['/', '\\'])
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>()
+        .join("\\")
+        .to_lowercase()
+}
+
+impl MemoryFileSystem {
+    pub(crate) fn new() -> MemoryFileSystem {
+        Default::default()
+    }
+
+    pub(crate) fn add_dir(&mut self, path: &Path) {
+        self.entries.insert(
+            normalize(path),
+            Entry::Dir {
+                path: path.to_path_buf(),
+            },
+        );
+    }
+
+    pub(crate) fn add_file(&mut self, path: &Path, contents: Option<String>) {
+        self.entries.insert(
+            normalize(path),
+            Entry::File {
+                path: path.to_path_buf(),
+                contents,
+                archive: None,
+                sha256: None,
+                header: None,
+            },
+        );
+    }
+
+    /// Records a speller file by its archive entries, SHA-256 and first bytes
+    /// rather than its contents.
+    pub(crate) fn add_speller_file(
+        &mut self,
+        path: &Path,
+        archive: Option<Vec<String>>,
+        sha256: Option<String>,
+        header: Option<Vec<u8>>,
+    ) {
+        self.entries.insert(
+            normalize(path),
+            Entry::File {
+                path: path.to_path_buf(),
+                contents: None,
+                archive,
+                sha256,
+                header,
+            },
+        );
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} was not captured", path.display()),
+    )
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let parent = normalize(path);
+        match self.entries.get(&parent) {
+            Some(Entry::Dir { .. }) => {}
+            _ => return Err(not_found(path)),
+        }
+
+        let prefix = format!("{}\\", parent);
+        Ok(self
+            .entries
+            .iter()
+            .filter(|(k, _)| k.starts_with(&prefix) && !k[prefix.len()..].contains('\\'))
+            .map(|(_, entry)| match entry {
+                Entry::Dir { path } | Entry::File { path, .. } => path.clone(),
+            })
+            .collect())
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        match self.entries.get(&normalize(path)) {
+            Some(Entry::File {
+                contents: Some(contents),
+                ..
+            }) => Ok(contents.clone()),
+            _ => Err(not_found(path)),
+        }
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.read_to_string(path).map(String::into_bytes)
+    }
+
+    fn archive_entries(&self, path: &Path) -> io::Result<Vec<String>> {
+        match self.entries.get(&normalize(path)) {
+            Some(Entry::File {
+                archive: Some(entries),
+                ..
+            }) => Ok(entries.clone()),
+            Some(Entry::File { .. }) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} was not captured as an archive", path.display()),
+            )),
+            _ => Err(not_found(path)),
+        }
+    }
+
+    fn sha256(&self, path: &Path) -> io::Result<String> {
+        match self.entries.get(&normalize(path)) {
+            Some(Entry::File {
+                sha256: Some(sha256),
+                ..
+            }) => Ok(sha256.clone()),
+            _ => Err(not_found(path)),
+        }
+    }
+
+    fn header(&self, path: &Path, len: usize) -> io::Result<Vec<u8>> {
+        let header = match self.entries.get(&normalize(path)) {
+            Some(Entry::File {
+                header: Some(header),
+                ..
+            }) => header.clone(),
+            _ => self.read(path)?,
+        };
+        Ok(header.into_iter().take(len).collect())
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        matches!(self.entries.get(&normalize(path)), Some(Entry::Dir { .. }))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.entries.contains_key(&normalize(path))
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::filesystem::FileSystem;
+use crate::zhfst;
+
+/// The bytes a BHFST file starts with. BHFST spellers are box-format
+/// containers, and every box file opens with this magic.
+pub(crate) const BHFST_MAGIC: &[u8] = b"\xffBOX";
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum Error {
+    #[error("Speller file not found: {0}")]
+    NotFound(PathBuf),
+
+    #[error("Speller file cannot be read: {0}")]
+    Unreadable(PathBuf, #[source] std::io::Error),
+
+    #[error("Speller file is not a BHFST file: {0}")]
+    NotBhfst(PathBuf),
+
+    #[error(transparent)]
+    Zhfst(#[from] zhfst::Error),
+}
+
+/// A speller file format DivvunSpell can load.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Format {
+    Zhfst,
+    Bhfst,
+}
+
+impl Format {
+    pub(crate) const ALL: [Format; 2] = [Format::Zhfst, Format::Bhfst];
+
+    fn name(self) -> &'static str {
+        match self {
+            Format::Zhfst => "zhfst",
+            Format::Bhfst => "bhfst",
+        }
+    }
+
+    /// The format a file's extension implies, if any.
+    pub(crate) fn from_path(path: &Path) -> Option<Format> {
+        let extension = path.extension()?.to_str()?;
+        Format::ALL
+            .iter()
+            .copied()
+            .find(|x| x.name().eq_ignore_ascii_case(extension))
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Format::ALL
+            .iter()
+            .copied()
+            .find(|x| x.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("Unknown speller format '{}'", s))
+    }
+}
+
+/// Checks `path` is a usable speller file in `format`. BHFST files are only
+/// checked to start with `BHFST_MAGIC`.
+pub(crate) fn validate(fs: &dyn FileSystem, path: &Path, format: Format) -> Result<(), Error> {
+    if !fs.exists(path) || fs.is_dir(path) {
+        return Err(Error::NotFound(path.to_path_buf()));
+    }
+
+    match format {
+        Format::Zhfst => zhfst::validate(fs, path)?,
+        Format::Bhfst => {
+            let header = fs
+                .header(path, BHFST_MAGIC.len())
+                .map_err(|e| Error::Unreadable(path.to_path_buf(), e))?;
+            if header != BHFST_MAGIC {
+                return Err(Error::NotBhfst(path.to_path_buf()));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::MemoryFileSystem;
+
+    #[test]
+    fn bhfst_files_must_start_with_the_box_magic() {
+        let mut fs = MemoryFileSystem::new();
+        let path = Path::new(r"C:\Spellers\se.bhfst");
+
+        fs.add_speller_file(path, None, None, Some(BHFST_MAGIC.to_vec()));
+        validate(&fs, path, Format::Bhfst).unwrap();
+
+        fs.add_speller_file(path, None, None, Some(b"PK\x03\x04".to_vec()));
+        assert!(matches!(
+            validate(&fs, path, Format::Bhfst),
+            Err(Error::NotBhfst(_))
+        ));
+
+        fs.add_speller_file(path, None, None, None);
+        assert!(matches!(
+            validate(&fs, path, Format::Bhfst),
+            Err(Error::Unreadable(..))
+        ));
+    }
+}
@@ -0,0 +1,68 @@
+use std::{fmt::Display, str::FromStr};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// An application refresh registers spellers with.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Host {
+    /// Microsoft Office, through its proofing tool Create records or per-user overrides.
+    Office,
+    /// LibreOffice, through the DivvunSpell extension. The extension reads every
+    /// speller in the Spellers key, so it is installed if any speller wants it.
+    LibreOffice,
+    /// The Windows spell checking API, through the WinDivvun spell checking
+    /// provider, which also reads every speller in the Spellers key.
+    Windows,
+}
+
+impl Host {
+    pub(crate) const ALL: [Host; 3] = [Host::Office, Host::LibreOffice, Host::Windows];
+
+    /// The hosts that read the Spellers key. They all see the same spellers,
+    /// so a speller is for all of them or for none.
+    pub(crate) const SPELLERS_KEY_READERS: [Host; 2] = [Host::LibreOffice, Host::Windows];
+
+    fn name(self) -> &'static str {
+        match self {
+            Host::Office => "office",
+            Host::LibreOffice => "libreoffice",
+            Host::Windows => "windows",
+        }
+    }
+}
+
+impl Display for Host {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Host {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Host::ALL
+            .iter()
+            .copied()
+            .find(|x| x.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("Unknown host '{}'", s))
+    }
+}
+
+/// Formats hosts for the registry and logs, as a comma-separated list.
+pub(crate) fn join(hosts: &[Host]) -> String {
+    hosts.iter().map(|x| x.name()).collect::<Vec<_>>().join(",")
+}
+
+/// Parses a list written by `join`, skipping hosts this version does not know.
+pub(crate) fn split(hosts: &str) -> Vec<Host> {
+    hosts
+        .split(',')
+        .filter(|x| !x.is_empty())
+        .filter_map(|x| x.parse().ok())
+        .collect()
+}
@@ -0,0 +1,234 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use crate::backend::{KeyPath, RegistryBackend, Value};
+use crate::capture::{capture_key, CapturedKey};
+use crate::libreoffice;
+use crate::plan::{self, Error, Step};
+use crate::reg::Scope;
+
+/// How to reverse a single completed step.
+#[derive(Debug, Clone)]
+pub(crate) enum Undo {
+    DeleteKey(KeyPath),
+    RestoreKeys(Vec<CapturedKey>),
+    SetValue(KeyPath, String, Value),
+    DeleteValue(KeyPath, String),
+    RemoveDir(PathBuf),
+    RemoveFile(PathBuf),
+    WriteFile(PathBuf, Vec<u8>),
+    /// Puts back the .oxt an unopkg remove took away, and installs it again.
+    Reinstall {
+        unopkg: PathBuf,
+        args: Vec<String>,
+        path: PathBuf,
+        data: Vec<u8>,
+    },
+    /// The step cannot be reversed, so rolling it back always fails.
+    Irreversible(String),
+}
+
+impl Display for Undo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Undo::DeleteKey(key) => write!(f, "Delete key {}", key),
+            Undo::RestoreKeys(keys) => match keys.first() {
+                Some(x) => write!(f, "Restore {} keys under {}", keys.len(), x.key),
+                None => f.write_str("Restore nothing"),
+            },
+            Undo::SetValue(key, name, value) => {
+                write!(f, "Set '{}' -> '{}' in {}", name, value, key)
+            }
+            Undo::DeleteValue(key, name) => write!(f, "Delete '{}' in {}", name, key),
+            Undo::RemoveDir(path) => write!(f, "Remove directory {}", path.display()),
+            Undo::RemoveFile(path) => write!(f, "Remove file {}", path.display()),
+            Undo::WriteFile(path, _) => write!(f, "Restore file {}", path.display()),
+            Undo::Reinstall { path, .. } => write!(f, "Reinstall {}", path.display()),
+            Undo::Irreversible(step) => write!(f, "Undo {}", step),
+        }
+    }
+}
+
+/// The outermost key that creating `key` would bring into existence, if any.
+fn first_missing_key(
+    registry: &dyn RegistryBackend,
+    key: &KeyPath,
+) -> Result<Option<KeyPath>, Error> {
+    if registry.key_exists(key)? {
+        return Ok(None);
+    }
+
+    let segments = key.path.split('\\').collect::<Vec<_>>();
+    for i in 1..=segments.len() {
+        let candidate = KeyPath::new(key.hive, key.view, segments[..i].join(r"\"));
+        if !registry.key_exists(&candidate)? {
+            return Ok(Some(candidate));
+        }
+    }
+
+    Ok(None)
+}
+
+/// The outermost directory that `create_dir_all(path)` would create, if any.
+fn first_missing_dir(path: &Path) -> Option<PathBuf> {
+    if path.exists() {
+        return None;
+    }
+
+    path.ancestors()
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .find(|x| !x.as_os_str().is_empty() && !x.exists())
+        .map(Path::to_path_buf)
+}
+
+/// How to undo an unopkg command: removing the extension spelli installed is
+/// undone by installing the .oxt it was installed from again. Installing
+/// cannot be undone, as the extension it replaced is already gone.
+fn unopkg_reinstall(unopkg: &Path, args: &[String]) -> Result<Option<Undo>, Error> {
+    if args.first().map(String::as_str) != Some("remove") {
+        return Ok(None);
+    }
+
+    let scope = if args.iter().any(|x| x == "--shared") {
+        Scope::Machine
+    } else {
+        Scope::User
+    };
+    let path = libreoffice::oxt_path(scope);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    Ok(Some(Undo::Reinstall {
+        unopkg: unopkg.to_path_buf(),
+        args: libreoffice::unopkg_args("add", &path.to_string_lossy(), scope),
+        data: std::fs::read(&path)?,
+        path,
+    }))
+}
+
+/// A record of every step applied so far, so that a failed apply can be reversed.
+#[derive(Debug, Default)]
+pub(crate) struct Journal {
+    entries: Vec<Undo>,
+}
+
+impl Journal {
+    pub(crate) fn new() -> Journal {
+        Default::default()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// How to undo `step`, if it changes anything. Must be worked out before
+    /// the step is applied, and only recorded once it succeeded.
+    pub(crate) fn undo(registry: &dyn RegistryBackend, step: &Step) -> Result<Option<Undo>, Error> {
+        let undo = match step {
+            Step::CreateKey { key } => match first_missing_key(registry, key)? {
+                Some(missing) => Undo::DeleteKey(missing),
+                None => return Ok(None),
+            },
+            Step::DeleteKey { key } => {
+                let mut keys = vec![];
+                capture_key(registry, key, true, &mut keys)?;
+                if keys.is_empty() {
+                    return Ok(None);
+                }
+                Undo::RestoreKeys(keys)
+            }
+            Step::SetValue { key, name, .. } | Step::SetCounter { key, name, .. } => {
+                match first_missing_key(registry, key)? {
+                    Some(missing) => Undo::DeleteKey(missing),
+                    None => match registry.value(key, name)? {
+                        Some(value) => Undo::SetValue(key.clone(), name.clone(), value),
+                        None => Undo::DeleteValue(key.clone(), name.clone()),
+                    },
+                }
+            }
+            Step::DeleteValue { key, name } => match registry.value(key, name)? {
+                Some(value) => Undo::SetValue(key.clone(), name.clone(), value),
+                None => return Ok(None),
+            },
+            Step::CreateDir { path } => match first_missing_dir(path) {
+                Some(missing) => Undo::RemoveDir(missing),
+                None => return Ok(None),
+            },
+            Step::WriteFile { path, .. } => {
+                if path.exists() {
+                    Undo::WriteFile(path.clone(), std::fs::read(path)?)
+                } else {
+                    Undo::RemoveFile(path.clone())
+                }
+            }
+            Step::RemoveFile { path } => {
+                if !path.exists() {
+                    return Ok(None);
+                }
+                Undo::WriteFile(path.clone(), std::fs::read(path)?)
+            }
+            Step::Unopkg { unopkg, args, .. } => match unopkg_reinstall(unopkg, args)? {
+                Some(undo) => undo,
+                None => Undo::Irreversible(step.to_string()),
+            },
+        };
+
+        Ok(Some(undo))
+    }
+
+    /// Records the undo of a step that was applied successfully.
+    pub(crate) fn record(&mut self, undo: Option<Undo>) {
+        self.entries.extend(undo);
+    }
+
+    /// Undoes every recorded step in reverse order. Carries on past failures
+    /// so as much as possible is put back, and returns the undos that failed,
+    /// including those of steps that cannot be undone.
+    pub(crate) fn rollback(self, registry: &dyn RegistryBackend) -> Vec<String> {
+        let mut failures = vec![];
+
+        for undo in self.entries.into_iter().rev() {
+            log::warn!("Rolling back: {}", undo);
+            let result = match &undo {
+                Undo::DeleteKey(key) => registry.delete_key(key).map_err(Error::from),
+                Undo::RestoreKeys(keys) => keys.iter().try_for_each(|captured| {
+                    registry.create_key(&captured.key)?;
+                    for (name, value) in captured.values.iter() {
+                        registry.set_value(&captured.key, name, value)?;
+                    }
+                    Ok(())
+                }),
+                Undo::SetValue(key, name, value) => {
+                    registry.set_value(key, name, value).map_err(Error::from)
+                }
+                Undo::DeleteValue(key, name) => {
+                    registry.delete_value(key, name).map_err(Error::from)
+                }
+                Undo::RemoveDir(path) => std::fs::remove_dir_all(path).map_err(Error::from),
+                Undo::RemoveFile(path) => std::fs::remove_file(path).map_err(Error::from),
+                Undo::WriteFile(path, data) => std::fs::write(path, data).map_err(Error::from),
+                Undo::Reinstall {
+                    unopkg,
+                    args,
+                    path,
+                    data,
+                } => std::fs::write(path, data)
+                    .map_err(Error::from)
+                    .and_then(|_| plan::run_unopkg(unopkg, args, false)),
+                Undo::Irreversible(step) => Err(Error::Irreversible(step.clone())),
+            };
+
+            if let Err(e) = result {
+                log::error!("Failed to roll back '{}': {:?}", undo, e);
+                failures.push(undo.to_string());
+            }
+        }
+
+        failures
+    }
+}
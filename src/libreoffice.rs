@@ -1,10 +1,46 @@
+use crate::backend::RegistryBackend;
+use crate::checksum;
+use crate::filesystem::FileSystem;
 use crate::refresh::get_candidate_regkeys;
+use crate::reg::Scope;
 use std::{path::PathBuf, process::Command};
 
-pub fn nuke() {
-    if let Some(unopkg) = find_unopkg() {
+pub(crate) const EXTENSION_ID: &str = "no.divvun.DivvunSpell";
+
+// The extension is only fetched for Windows builds.
+#[cfg(windows)]
+pub(crate) const OXT_DATA: &[u8] = include_bytes!("../divvunspell-libreoffice.oxt");
+#[cfg(not(windows))]
+pub(crate) const OXT_DATA: &[u8] = &[];
+
+/// Where the extension is written for unopkg to install it from.
+pub(crate) fn oxt_path(scope: Scope) -> PathBuf {
+    get_speller_install_directory(scope).join("divvunspell.oxt")
+}
+
+/// Whether the extension written for `scope` is the one spelli carries, so
+/// there is nothing to reinstall.
+pub(crate) fn is_current(fs: &dyn FileSystem, scope: Scope) -> bool {
+    fs.sha256(&oxt_path(scope))
+        .map(|x| x == checksum::sha256(OXT_DATA))
+        .unwrap_or(false)
+}
+
+/// Arguments for an unopkg command. Machine-wide installs are shared between
+/// all users, per-user installs go to the profile of the user running unopkg.
+pub(crate) fn unopkg_args(command: &str, target: &str, scope: Scope) -> Vec<String> {
+    let mut args = vec![command.to_string()];
+    if scope == Scope::Machine {
+        args.push("--shared".to_string());
+    }
+    args.push(target.to_string());
+    args
+}
+
+pub fn nuke(registry: &dyn RegistryBackend, fs: &dyn FileSystem, scope: Scope, extension_id: &str) {
+    if let Some(unopkg) = find_unopkg(registry, fs) {
         let result = Command::new(&unopkg)
-            .args(&["remove", "--shared", "no.divvun.DivvunSpell"])
+            .args(unopkg_args("remove", extension_id, scope))
             .output();
 
         match result {
@@ -21,7 +57,7 @@ pub fn nuke() {
         log::info!("Unable to find unopkg, not uninstalling from libreoffice");
     }
 
-    let install_path = get_speller_install_directory();
+    let install_path = get_speller_install_directory(scope);
     if install_path.exists() {
         if let Err(e) = std::fs::remove_dir_all(&install_path) {
             log::info!("Unable to remove {:?}: {}", install_path, e)
@@ -31,8 +67,8 @@ pub fn nuke() {
     }
 }
 
-pub fn find_unopkg() -> Option<PathBuf> {
-    let libreoffice_install = get_candidate_regkeys()
+pub fn find_unopkg(registry: &dyn RegistryBackend, fs: &dyn FileSystem) -> Option<PathBuf> {
+    let libreoffice_install = get_candidate_regkeys(registry)
         .iter()
         .find_map(|candidate| candidate.validate_libreoffice());
 
@@ -50,7 +86,7 @@ pub fn find_unopkg() -> Option<PathBuf> {
         .join("unopkg.com");
     log::debug!("Checking if unopkg exists at: {:?}", &unopkg_path);
 
-    if !unopkg_path.exists() {
+    if !fs.exists(&unopkg_path) {
         log::error!(
             "Couldn't find unopkg at {:?}. Is the installation corrupt?",
             &unopkg_path
@@ -61,7 +97,22 @@ pub fn find_unopkg() -> Option<PathBuf> {
     Some(unopkg_path)
 }
 
-pub fn get_speller_install_directory() -> PathBuf {
-    let program_files_path = windirs::known_folder_path(windirs::FolderId::ProgramFiles).unwrap();
-    program_files_path.join("DivvunSpell LibreOffice")
+#[cfg(windows)]
+pub fn get_speller_install_directory(scope: Scope) -> PathBuf {
+    match scope {
+        Scope::Machine => {
+            let program_files_path =
+                windirs::known_folder_path(windirs::FolderId::ProgramFiles).unwrap();
+            program_files_path.join("DivvunSpell LibreOffice")
+        }
+        Scope::User => crate::reg::user_local_dir().join("DivvunSpell LibreOffice"),
+    }
+}
+
+#[cfg(not(windows))]
+pub fn get_speller_install_directory(scope: Scope) -> PathBuf {
+    match scope {
+        Scope::Machine => pathos::system::app_data_dir("DivvunSpell LibreOffice"),
+        Scope::User => crate::reg::user_local_dir().join("DivvunSpell LibreOffice"),
+    }
 }
@@ -0,0 +1,526 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+use unic_langid::LanguageIdentifier;
+
+use crate::filesystem::FileSystem;
+use crate::format;
+use crate::package::{self, Candidate, Speller, SpellerToml, SPELLER_TOML};
+use crate::{checksum, register};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Severity {
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// One problem found in a speller package. `line` and `column` are 1-based.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct Diagnostic {
+    pub(crate) severity: Severity,
+    pub(crate) file: PathBuf,
+    pub(crate) line: Option<usize>,
+    pub(crate) column: Option<usize>,
+    pub(crate) message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+            if let Some(column) = self.column {
+                write!(f, ":{}", column)?;
+            }
+        }
+        write!(f, ": {}: {}", self.severity, self.message)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub(crate) struct Report {
+    pub(crate) diagnostics: Vec<Diagnostic>,
+}
+
+impl Report {
+    pub(crate) fn count(&self, severity: Severity) -> usize {
+        self.diagnostics
+            .iter()
+            .filter(|x| x.severity == severity)
+            .count()
+    }
+
+    /// Whether CI should fail: any error, or any warning when they are denied.
+    pub(crate) fn fails(&self, deny_warnings: bool) -> bool {
+        self.count(Severity::Error) > 0 || (deny_warnings && self.count(Severity::Warning) > 0)
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for diagnostic in self.diagnostics.iter() {
+            writeln!(f, "{}", diagnostic)?;
+        }
+        writeln!(
+            f,
+            "{} errors, {} warnings.",
+            self.count(Severity::Error),
+            self.count(Severity::Warning)
+        )
+    }
+}
+
+/// Where `key` is written in `table` (`None` for the top level), as a 1-based
+/// line and column. toml keeps no positions for values, so this scans the text.
+fn locate(text: &str, table: Option<&str>, key: &str) -> Option<(usize, usize)> {
+    fn unquote(s: &str) -> &str {
+        s.trim().trim_matches(|c| c == '"' || c == '\'')
+    }
+
+    let mut current: Option<&str> = None;
+    for (i, line) in text.lines().enumerate() {
+        let trimmed = line.trim_start();
+        let column = line.len() - trimmed.len() + 1;
+
+        if trimmed.starts_with('[') {
+            let header = trimmed.trim_start_matches('[');
+            let header = unquote(header.split(']').next().unwrap_or_default());
+            if table.is_none() && header == key {
+                return Some((i + 1, column));
+            }
+            current = Some(header);
+            continue;
+        }
+
+        if current != table {
+            continue;
+        }
+        if let Some((name, _)) = trimmed.split_once('=') {
+            if unquote(name) == key {
+                return Some((i + 1, column));
+            }
+        }
+    }
+
+    None
+}
+
+struct Linter<'a> {
+    file: PathBuf,
+    text: &'a str,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Linter<'a> {
+    fn push(&mut self, severity: Severity, at: Option<(usize, usize)>, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            file: self.file.clone(),
+            line: at.map(|x| x.0),
+            column: at.map(|x| x.1),
+            message,
+        });
+    }
+
+    fn error(&mut self, at: Option<(usize, usize)>, message: String) {
+        self.push(Severity::Error, at, message);
+    }
+
+    fn warning(&mut self, at: Option<(usize, usize)>, message: String) {
+        self.push(Severity::Warning, at, message);
+    }
+
+    fn at(&self, table: Option<&str>, key: &str) -> Option<(usize, usize)> {
+        locate(self.text, table, key)
+    }
+
+    /// Where the dotted key `path` is written. Keys inside inline tables
+    /// cannot be found, so this falls back to the nearest enclosing key.
+    fn at_path(&self, path: &str) -> Option<(usize, usize)> {
+        let mut rest = path;
+        loop {
+            match rest.rsplit_once('.') {
+                Some((table, key)) => {
+                    if let Some(at) = self.at(Some(table), key) {
+                        return Some(at);
+                    }
+                    rest = table;
+                }
+                None => return self.at(None, rest),
+            }
+        }
+    }
+
+    /// Deserializes the speller.toml, reporting syntax errors, type errors and
+    /// every key the schema does not know.
+    fn parse(&mut self) -> Option<SpellerToml> {
+        let mut unknown = vec![];
+        let result: Result<SpellerToml, toml::de::Error> =
+            serde_ignored::deserialize(&mut toml::Deserializer::new(self.text), |path| {
+                unknown.push(path.to_string())
+            });
+
+        let speller_toml = match result {
+            Ok(v) => v,
+            Err(e) => {
+                let at = e.line_col().map(|(line, col)| (line + 1, col + 1));
+                let message = e.to_string();
+                // The message ends with the position, which is already shown.
+                let message = match at {
+                    Some((line, col)) => message
+                        .trim_end_matches(&format!(" at line {} column {}", line, col))
+                        .to_string(),
+                    None => message,
+                };
+                self.error(at, message);
+                return None;
+            }
+        };
+
+        for path in unknown {
+            self.warning(self.at_path(&path), format!("Unknown key '{}'", path));
+        }
+
+        if let Err(e @ package::Error::UnsupportedVersion(_)) = speller_toml.check_version() {
+            self.error(self.at(None, "version"), e.to_string());
+            return None;
+        }
+
+        for (table, key, e) in speller_toml.version_2_keys() {
+            self.error(self.at(table, key), e.to_string());
+        }
+
+        Some(speller_toml)
+    }
+
+    fn lint_spellers(&mut self, fs: &dyn FileSystem, dir: &Path, speller_toml: &SpellerToml) {
+        // Lowercased tag -> the entry it came from, as the registry compares them
+        let mut entries: BTreeMap<String, String> = BTreeMap::new();
+        let mut derived: BTreeMap<String, String> = BTreeMap::new();
+
+        for (tag, speller) in speller_toml.spellers.iter() {
+            let at = self.at(Some("spellers"), tag);
+
+            if speller.hosts.is_empty() {
+                self.warning(
+                    at,
+                    format!(
+                        "'{}' has empty `hosts`, so no host will be registered to use it",
+                        tag
+                    ),
+                );
+            }
+
+            let lang_id: LanguageIdentifier = match tag.parse() {
+                Ok(v) => v,
+                Err(e) => {
+                    self.error(
+                        at,
+                        format!("'{}' is not a valid BCP 47 language tag: {}", tag, e),
+                    );
+                    continue;
+                }
+            };
+
+            let canonical = lang_id.to_string();
+            if &canonical != tag {
+                self.warning(
+                    at,
+                    format!("'{}' is not canonical, write it as '{}'", tag, canonical),
+                );
+            }
+
+            match entries.insert(canonical.to_lowercase(), tag.clone()) {
+                Some(other) => self.error(
+                    at,
+                    format!("'{}' is the same language tag as '{}'", tag, other),
+                ),
+                None => match register::derive_lang_id_keys(lang_id) {
+                    Ok(mut keys) => {
+                        keys.extend(self.lint_aliases(tag, at, speller, &keys));
+                        for key in keys {
+                            if let Some(other) = derived.insert(key.to_lowercase(), tag.clone()) {
+                                self.warning(
+                                    at,
+                                    format!(
+                                        "'{}' is provided by both '{}' and '{}'; '{}' wins",
+                                        key, other, tag, tag
+                                    ),
+                                );
+                            }
+                        }
+                    }
+                    Err(e) => self.error(
+                        at,
+                        format!("Cannot derive registry keys for '{}': {}", tag, e),
+                    ),
+                },
+            }
+
+            for candidate in speller.candidates.iter() {
+                self.lint_candidate(fs, dir, tag, at, candidate);
+            }
+        }
+    }
+
+    /// Checks the aliases of `tag`, returning the ones refresh would add to `keys`.
+    fn lint_aliases(
+        &mut self,
+        tag: &str,
+        at: Option<(usize, usize)>,
+        speller: &Speller,
+        keys: &[String],
+    ) -> Vec<String> {
+        let aliases = match speller.alias_tags() {
+            Ok(v) => v,
+            Err(e) => {
+                self.error(at, format!("Invalid alias of '{}': {}", tag, e));
+                return vec![];
+            }
+        };
+
+        let mut out: Vec<String> = vec![];
+        for alias in aliases {
+            let canonical = match alias.parse::<LanguageIdentifier>() {
+                Ok(v) => v.to_string(),
+                Err(e) => {
+                    self.error(
+                        at,
+                        format!(
+                            "Alias '{}' of '{}' is not a valid BCP 47 language tag: {}",
+                            alias, tag, e
+                        ),
+                    );
+                    continue;
+                }
+            };
+            if canonical != alias {
+                self.warning(
+                    at,
+                    format!(
+                        "Alias '{}' of '{}' is not canonical, write it as '{}'",
+                        alias, tag, canonical
+                    ),
+                );
+            }
+
+            if keys.iter().any(|x| x.eq_ignore_ascii_case(&canonical)) {
+                self.warning(
+                    at,
+                    format!("Alias '{}' of '{}' is already derived from it", alias, tag),
+                );
+            } else if out.iter().any(|x| x.eq_ignore_ascii_case(&canonical)) {
+                self.warning(
+                    at,
+                    format!("Alias '{}' of '{}' is listed more than once", alias, tag),
+                );
+            } else {
+                out.push(canonical);
+            }
+        }
+        out
+    }
+
+    fn lint_candidate(
+        &mut self,
+        fs: &dyn FileSystem,
+        dir: &Path,
+        tag: &str,
+        at: Option<(usize, usize)>,
+        candidate: &Candidate,
+    ) {
+        let format = match candidate.format() {
+            Some(v) => v,
+            None => {
+                self.error(
+                    at,
+                    format!(
+                        "Unknown format of '{}' for '{}', declare its `format`",
+                        candidate.file, tag
+                    ),
+                );
+                return;
+            }
+        };
+
+        let path = dir.join(&candidate.file);
+        // Only the first bytes are read, to tell an unreadable file from a bad one
+        if fs.exists(&path) && !fs.is_dir(&path) {
+            if let Err(e) = fs.header(&path, format::BHFST_MAGIC.len()) {
+                self.error(
+                    at,
+                    format!(
+                        "Speller file for '{}' cannot be read: {}: {}",
+                        tag,
+                        path.display(),
+                        e
+                    ),
+                );
+                return;
+            }
+        }
+
+        if let Err(e) = format::validate(fs, &path, format) {
+            let message = match std::error::Error::source(&e) {
+                Some(source) => format!("{}: {}", e, source),
+                None => e.to_string(),
+            };
+            self.error(at, format!("Speller file for '{}': {}", tag, message));
+        } else if let Some(sha256) = &candidate.sha256 {
+            if let Err(e) = checksum::verify(fs, &path, sha256) {
+                self.error(at, format!("Speller file for '{}': {}", tag, e));
+            }
+        }
+    }
+}
+
+/// Checks the speller package in `dir` without touching the registry.
+pub(crate) fn lint(fs: &dyn FileSystem, dir: &Path) -> Vec<Diagnostic> {
+    let file = dir.join(SPELLER_TOML);
+    let text = match fs.read_to_string(&file) {
+        Ok(v) => v,
+        Err(e) => {
+            return vec![Diagnostic {
+                severity: Severity::Error,
+                file,
+                line: None,
+                column: None,
+                message: format!("Cannot read {}: {}", SPELLER_TOML, e),
+            }];
+        }
+    };
+
+    let mut linter = Linter {
+        file,
+        text: &text,
+        diagnostics: vec![],
+    };
+
+    if let Some(speller_toml) = linter.parse() {
+        linter.lint_spellers(fs, dir, &speller_toml);
+    }
+
+    let mut diagnostics = linter.diagnostics;
+    diagnostics.sort_by_key(|x| (x.line, x.column));
+    diagnostics
+}
+
+/// Lints every package directory in `dirs`.
+pub(crate) fn lint_all(fs: &dyn FileSystem, dirs: &[PathBuf]) -> Report {
+    Report {
+        diagnostics: dirs.iter().flat_map(|dir| lint(fs, dir)).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::MemoryFileSystem;
+    use crate::testing;
+
+    /// Lints a package with `text` as its speller.toml and no speller files.
+    fn lint_text(text: &str) -> Vec<(Severity, Option<usize>, Option<usize>)> {
+        let (_, mut fs) = testing::machine();
+        let dir = testing::add_package(&mut fs, "se", text, &[]);
+        lint(&fs, &dir)
+            .into_iter()
+            .map(|x| (x.severity, x.line, x.column))
+            .collect()
+    }
+
+    #[test]
+    fn syntax_errors_point_at_the_error() {
+        assert_eq!(
+            lint_text("[spellers]\nse = \"se.zhfst\n"),
+            vec![(Severity::Error, Some(2), Some(15))]
+        );
+    }
+
+    #[test]
+    fn problems_point_at_their_entry() {
+        let text = "version = 2\n\n[spellers]\nse = \"se.zhfst\"\n  sma = \"sma.zhfst\"\n";
+        assert_eq!(
+            lint_text(text),
+            vec![
+                (Severity::Error, Some(4), Some(1)),
+                (Severity::Error, Some(5), Some(3)),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_keys_in_inline_tables_point_at_the_entry() {
+        let text = "version = 2\n[spellers]\n\"se\" = { file = \"se.zhfst\", colour = \"blue\" }\n";
+        assert_eq!(
+            lint_text(text),
+            vec![
+                (Severity::Warning, Some(3), Some(1)),
+                (Severity::Error, Some(3), Some(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn unsupported_versions_point_at_the_version() {
+        let text = "  version = 3\n[spellers]\nse = \"se.zhfst\"\n";
+        assert_eq!(lint_text(text), vec![(Severity::Error, Some(1), Some(3))]);
+    }
+
+    #[test]
+    fn version_2_keys_in_version_1_files_point_at_the_key() {
+        let text = "[package]\nname = \"North Sami\"\n[spellers]\nse = { file = \"se.zhfst\" }\n";
+        assert_eq!(
+            lint_text(text),
+            vec![
+                (Severity::Error, Some(1), Some(1)),
+                (Severity::Error, Some(4), Some(1)),
+                (Severity::Error, Some(4), Some(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn unreadable_speller_files_are_reported() {
+        let (_, mut fs) = testing::machine();
+        let text = "[spellers]\nse = \"se.zhfst\"\nsma = \"sma.zhfst\"\n";
+        let dir = testing::add_package(&mut fs, "se", text, &["se.zhfst"]);
+        let entries = [
+            "index.xml",
+            "acceptor.default.hfst",
+            "errmodel.default.hfst",
+        ];
+        fs.add_speller_file(
+            &dir.join("sma.zhfst"),
+            Some(entries.iter().map(|x| x.to_string()).collect()),
+            None,
+            None,
+        );
+
+        let diagnostics = lint(&fs, &dir);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, Some(3));
+        assert!(diagnostics[0].message.contains("cannot be read"));
+    }
+
+    #[test]
+    fn missing_speller_toml_has_no_position() {
+        let fs = MemoryFileSystem::new();
+        let diagnostics = lint(&fs, Path::new(testing::SPELLER_ROOT));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, None);
+    }
+}
@@ -1,19 +1,112 @@
-pub(crate) fn list() {
-    let key = crate::reg::open_key().unwrap();
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 
+use crate::backend::RegistryBackend;
+use crate::filesystem::FileSystem;
+use crate::package::{PackageMetadata, SpellerToml};
+use crate::reg::{Origin, Scope, Source};
+
+const SCOPES: [Scope; 2] = [Scope::Machine, Scope::User];
+
+pub(crate) fn list(registry: &dyn RegistryBackend, fs: &dyn FileSystem) {
     println!("Registered spellers:");
 
-    let results = key
-        .values()
-        .map(|x| x.unwrap().into_inner())
-        .collect::<Vec<_>>();
+    // Tag (lowercased, as the registry compares them) -> display tag, value per scope
+    let mut rows: BTreeMap<String, (String, [Option<String>; 2])> = BTreeMap::new();
+    let mut origins: [BTreeMap<String, Origin>; 2] = Default::default();
+
+    for (i, scope) in SCOPES.iter().enumerate() {
+        // Hidden tags are only for Office, but are registered all the same
+        let keys = [
+            crate::reg::spellers_key(*scope),
+            crate::reg::hidden_spellers_key(*scope),
+        ];
+        let results = keys.iter().flat_map(|key| match registry.values(key) {
+            Err(crate::reg::Error::NotFound(_)) => vec![],
+            result => result.unwrap(),
+        });
+
+        for (name, value) in results {
+            let row = rows
+                .entry(name.to_lowercase())
+                .or_insert_with(|| (name.clone(), [None, None]));
+            row.1[i] = Some(value.to_string());
+        }
+
+        origins[i] = crate::reg::origins(registry, *scope)
+            .unwrap()
+            .into_iter()
+            .map(|(tag, origin)| (tag.to_lowercase(), origin))
+            .collect();
+    }
 
-    if results.is_empty() {
+    if rows.is_empty() {
         println!("  - No spellers registered.");
         return;
     }
 
-    for (name, value) in results {
-        println!(" - {} -> {}", name.to_string_lossy(), value);
+    // Package directory -> what its speller.toml says about it, if readable
+    let mut packages: BTreeMap<PathBuf, Option<PackageMetadata>> = BTreeMap::new();
+
+    let tag_width = rows
+        .values()
+        .map(|(tag, _)| tag.len())
+        .chain(std::iter::once("Tag".len()))
+        .max()
+        .unwrap_or_default();
+    let machine_width = rows
+        .values()
+        .filter_map(|(_, values)| values[0].as_ref().map(|x| x.len()))
+        .chain(std::iter::once("Machine".len()))
+        .max()
+        .unwrap_or_default();
+
+    println!(
+        " {:<tag_width$}  {:<machine_width$}  User",
+        "Tag",
+        "Machine",
+        tag_width = tag_width,
+        machine_width = machine_width
+    );
+
+    for (id, (tag, values)) in rows.iter() {
+        let [machine, user] = values;
+        println!(
+            " {:<tag_width$}  {:<machine_width$}  {}",
+            tag,
+            machine.as_deref().unwrap_or("-"),
+            user.as_deref().unwrap_or("-"),
+            tag_width = tag_width,
+            machine_width = machine_width
+        );
+
+        for (i, scope) in SCOPES.iter().enumerate() {
+            if let Some(origin) = origins[i].get(id) {
+                println!(
+                    " {:<tag_width$}    {:<7}  {}",
+                    "",
+                    scope,
+                    origin,
+                    tag_width = tag_width
+                );
+
+                if let Source::Package { dir, .. } = &origin.source {
+                    let metadata = packages.entry(dir.clone()).or_insert_with(|| {
+                        SpellerToml::load(fs, dir)
+                            .ok()
+                            .map(|x| x.package)
+                            .filter(|x| !x.is_empty())
+                    });
+                    if let Some(metadata) = metadata {
+                        println!(
+                            " {:<tag_width$}             {}",
+                            "",
+                            metadata,
+                            tag_width = tag_width
+                        );
+                    }
+                }
+            }
+        }
     }
 }
@@ -1,17 +1,91 @@
+mod backend;
+mod capture;
+mod checksum;
+mod clock;
+mod config;
 mod deregister;
+mod detect;
+mod diff;
+mod export;
+mod filesystem;
+mod format;
+mod host;
+mod journal;
 mod libreoffice;
+mod lint;
 mod list;
+mod mso;
+mod package;
+mod plan;
+mod purge;
 mod refresh;
 mod reg;
 mod register;
+mod snapshot;
+#[cfg(test)]
+mod testing;
+mod zhfst;
 
+use std::path::PathBuf;
+
+use backend::RegistryBackend;
+use filesystem::{FileSystem, RealFileSystem};
 use gumdrop::Options;
+use reg::Scope;
 
 #[derive(Debug, Options)]
 struct Args {
     #[options(help = "show usage help")]
     help: bool,
 
+    #[options(
+        no_short,
+        meta = "FILE",
+        help = "Run against a capture made by `capture` instead of this machine"
+    )]
+    replay: Option<PathBuf>,
+
+    #[options(
+        no_short,
+        help = "Manage spellers for the current user only, without administrator rights"
+    )]
+    user: bool,
+
+    #[options(
+        no_short,
+        meta = "FILE",
+        help = "Read configuration from FILE instead of the default spelli.toml"
+    )]
+    config: Option<PathBuf>,
+
+    #[options(
+        no_short,
+        meta = "DIR",
+        help = "Also scan DIR for speller packages; may be repeated, earlier ones win"
+    )]
+    speller_dir: Vec<PathBuf>,
+
+    #[options(
+        no_short,
+        meta = "DAYS",
+        help = "Days to keep Delete records of deregistered spellers (default: 30)"
+    )]
+    tombstone_retention: Option<u64>,
+
+    #[options(
+        no_short,
+        meta = "PATH",
+        help = "32-bit DivvunSpell MSO DLL to register with Office"
+    )]
+    mso_dll_32: Option<String>,
+
+    #[options(
+        no_short,
+        meta = "PATH",
+        help = "64-bit DivvunSpell MSO DLL to register with Office"
+    )]
+    mso_dll_64: Option<String>,
+
     #[options(command)]
     command: Option<Command>,
 }
@@ -21,11 +95,74 @@ enum Command {
     #[options(help = "Refresh registry keys for registered spellers")]
     Refresh(RefreshArgs),
 
+    #[options(help = "Register a speller file for a language tag, kept across refreshes")]
+    Register(RegisterArgs),
+
+    #[options(help = "Deregister a language tag, kept across refreshes")]
+    Deregister(DeregisterArgs),
+
     #[options(help = "List registered spellers")]
     List(ListArgs),
 
     #[options(help = "Delete all registered spellers")]
     Nuke(NukeArgs),
+
+    #[options(help = "Remove every trace of WinDivvun spellers from the registry")]
+    Purge(PurgeArgs),
+
+    #[options(help = "Write the changes a refresh would make to a JSON plan")]
+    Plan(PlanArgs),
+
+    #[options(help = "Apply a JSON plan created by `plan`")]
+    Apply(ApplyArgs),
+
+    #[options(help = "Export the registry state a refresh would leave as a .reg file")]
+    Export(ExportArgs),
+
+    #[options(help = "Compare registered spellers against the installed speller packages")]
+    Diff(DiffArgs),
+
+    #[options(help = "Show detected Office and LibreOffice installations")]
+    Detect(DetectArgs),
+
+    #[options(help = "Capture everything spelli reads from this machine into a file")]
+    Capture(CaptureArgs),
+
+    #[options(help = "List snapshots of spelli-managed registry keys")]
+    Snapshots(SnapshotsArgs),
+
+    #[options(help = "Restore spelli-managed registry keys from a snapshot")]
+    Restore(RestoreArgs),
+
+    #[options(help = "Inspect spelli's configuration")]
+    Config(ConfigArgs),
+
+    #[options(help = "Check speller package directories for problems")]
+    Lint(LintArgs),
+
+    #[options(help = "Print the JSON Schema of speller.toml")]
+    Schema(SchemaArgs),
+}
+
+impl Command {
+    /// Whether the subcommand prints a report on stdout that may be parsed.
+    fn is_report(&self) -> bool {
+        matches!(self, Command::Lint(_) | Command::Diff(_))
+    }
+
+    /// Whether the subcommand changes the registry, rather than only reading it.
+    fn is_mutating(&self) -> bool {
+        matches!(
+            self,
+            Command::Refresh(_)
+                | Command::Register(_)
+                | Command::Deregister(_)
+                | Command::Nuke(_)
+                | Command::Purge(_)
+                | Command::Apply(_)
+                | Command::Restore(_)
+        )
+    }
 }
 
 #[derive(Debug, Options)]
@@ -67,9 +204,138 @@ struct NukeArgs {
     help: bool,
 }
 
-fn setup_logger() {
-    let log_path = pathos::system::app_log_dir("WinDivvun");
-    match std::fs::create_dir_all(&log_path) {
+#[derive(Debug, Options)]
+struct PurgeArgs {
+    #[options(help = "show usage help")]
+    help: bool,
+
+    #[options(
+        help = "Purge without waiting for Office to process the Delete records. Without it, only the Office of the user running purge is waited for"
+    )]
+    force: bool,
+}
+
+#[derive(Debug, Options)]
+struct PlanArgs {
+    #[options(help = "show usage help")]
+    help: bool,
+
+    #[options(required, help = "Path to write the plan to")]
+    output: std::path::PathBuf,
+}
+
+#[derive(Debug, Options)]
+struct ApplyArgs {
+    #[options(help = "show usage help")]
+    help: bool,
+
+    #[options(free, required, help = "Path to the plan to apply")]
+    path: std::path::PathBuf,
+}
+
+#[derive(Debug, Options)]
+struct ExportArgs {
+    #[options(help = "show usage help")]
+    help: bool,
+
+    #[options(required, help = "Path to write the .reg file to")]
+    output: std::path::PathBuf,
+}
+
+#[derive(Debug, Options)]
+struct DiffArgs {
+    #[options(help = "show usage help")]
+    help: bool,
+
+    #[options(help = "Write the report as JSON")]
+    json: bool,
+
+    #[options(help = "Path to write the report to instead of stdout")]
+    output: Option<std::path::PathBuf>,
+}
+
+#[derive(Debug, Options)]
+struct DetectArgs {
+    #[options(help = "show usage help")]
+    help: bool,
+}
+
+#[derive(Debug, Options)]
+struct CaptureArgs {
+    #[options(help = "show usage help")]
+    help: bool,
+
+    #[options(required, help = "Path to write the capture to")]
+    output: std::path::PathBuf,
+}
+
+#[derive(Debug, Options)]
+struct SnapshotsArgs {
+    #[options(help = "show usage help")]
+    help: bool,
+}
+
+#[derive(Debug, Options)]
+struct RestoreArgs {
+    #[options(help = "show usage help")]
+    help: bool,
+
+    #[options(
+        meta = "ID",
+        help = "Snapshot to restore, defaults to the most recent one"
+    )]
+    snapshot: Option<String>,
+}
+
+#[derive(Debug, Options)]
+struct ConfigArgs {
+    #[options(help = "show usage help")]
+    help: bool,
+
+    #[options(command)]
+    command: Option<ConfigCommand>,
+}
+
+#[derive(Debug, Options)]
+enum ConfigCommand {
+    #[options(help = "Print the effective configuration and where each value came from")]
+    Show(ConfigShowArgs),
+}
+
+#[derive(Debug, Options)]
+struct ConfigShowArgs {
+    #[options(help = "show usage help")]
+    help: bool,
+}
+
+#[derive(Debug, Options)]
+struct LintArgs {
+    #[options(help = "show usage help")]
+    help: bool,
+
+    #[options(free, required, help = "Speller package directories to check")]
+    dirs: Vec<PathBuf>,
+
+    #[options(help = "Fail on warnings as well as errors")]
+    deny_warnings: bool,
+
+    #[options(help = "Write the diagnostics as JSON")]
+    json: bool,
+}
+
+#[derive(Debug, Options)]
+struct SchemaArgs {
+    #[options(help = "show usage help")]
+    help: bool,
+
+    #[options(help = "Path to write the schema to instead of stdout")]
+    output: Option<std::path::PathBuf>,
+}
+
+/// Logs everything to spelli.log, and `console` and above to stderr, leaving
+/// stdout to the output of the subcommand.
+fn setup_logger(log_path: &std::path::Path, console: log::LevelFilter) {
+    match std::fs::create_dir_all(log_path) {
         Ok(_) => {}
         Err(e) => {
             eprintln!("{:?}", e);
@@ -89,7 +355,11 @@ fn setup_logger() {
             ))
         })
         .level(log::LevelFilter::Trace)
-        .chain(std::io::stdout())
+        .chain(
+            fern::Dispatch::new()
+                .level(console)
+                .chain(std::io::stderr()),
+        )
         .chain(fern::log_file(log_path.join("spelli.log")).unwrap())
         .apply()
         .unwrap();
@@ -97,8 +367,18 @@ fn setup_logger() {
 
 fn main() {
     let args = Args::parse_args_default_or_exit();
-    setup_logger();
+    let scope = if args.user {
+        Scope::User
+    } else {
+        Scope::Machine
+    };
 
+    let overrides = config::Overrides {
+        speller_roots: args.speller_dir.clone(),
+        mso_dll_32: args.mso_dll_32.clone(),
+        mso_dll_64: args.mso_dll_64.clone(),
+        tombstone_retention_days: args.tombstone_retention,
+    };
     let command = match args.command {
         Some(v) => v,
         None => {
@@ -107,16 +387,227 @@ fn main() {
         }
     };
 
+    // Schema and lint do not use the configuration, so a broken spelli.toml
+    // must not stop them
+    let mut config = if matches!(command, Command::Schema(_) | Command::Lint(_)) {
+        config::Config::defaults(scope)
+    } else {
+        match config::Config::load(scope, args.config.as_deref(), &overrides) {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("{}", e);
+                if let Some(source) = std::error::Error::source(&e) {
+                    eprintln!("{}", source);
+                }
+                std::process::exit(1);
+            }
+        }
+    };
+
+    // Reports are read by people and scripts, so only problems are shown with them
+    let console = if command.is_report() {
+        log::LevelFilter::Warn
+    } else {
+        log::LevelFilter::Trace
+    };
+    setup_logger(&config.log_dir.value, console);
+
+    let clock = clock::SystemClock;
+
+    // The schema reads nothing from the machine, so nothing needs detecting
+    if let Command::Schema(args) = &command {
+        let text = serde_json::to_string_pretty(&package::SpellerToml::schema()).unwrap();
+        match &args.output {
+            Some(path) => {
+                std::fs::write(path, text).unwrap();
+                log::info!("Wrote schema to {}", path.display());
+            }
+            None => println!("{}", text),
+        }
+        return;
+    }
+
+    // Lint only reads the packages it is given, not the machine
+    if let Command::Lint(lint) = &command {
+        if args.replay.is_some() {
+            eprintln!("This subcommand cannot be used with --replay.");
+            std::process::exit(1);
+        }
+
+        let report = lint::lint_all(&RealFileSystem, &lint.dirs);
+        if lint.json {
+            println!("{}", serde_json::to_string_pretty(&report).unwrap());
+        } else {
+            print!("{}", report);
+        }
+
+        if report.fails(lint.deny_warnings) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let (registry, fs, speller_roots): (
+        Box<dyn RegistryBackend>,
+        Box<dyn FileSystem>,
+        Vec<PathBuf>,
+    ) = match &args.replay {
+        Some(path) => {
+            if command.is_mutating() {
+                eprintln!("This subcommand cannot be used with --replay.");
+                std::process::exit(1);
+            }
+
+            let capture = capture::Capture::load(path).unwrap();
+            log::info!("Replaying capture from {}", path.display());
+            (
+                Box::new(capture.registry().unwrap()),
+                Box::new(capture.files.clone()),
+                capture.speller_roots,
+            )
+        }
+        None => {
+            let registry = match backend::system() {
+                Some(v) => v,
+                None if command.is_mutating() => {
+                    eprintln!("This subcommand needs the Windows registry, which this platform does not have.");
+                    std::process::exit(1);
+                }
+                None => {
+                    log::warn!("No Windows registry on this platform; reading an empty one.");
+                    Box::new(backend::MemoryRegistry::new())
+                }
+            };
+            (registry, Box::new(RealFileSystem), config.speller_roots())
+        }
+    };
+
+    config.detect_mso_dlls(&*registry);
+    let options = config.refresh_options();
+
     match command {
         Command::Refresh(_args) => {
-            refresh::refresh().unwrap();
+            refresh::refresh(&*registry, &*fs, &clock, &speller_roots, scope, &options).unwrap();
+        }
+        Command::Register(args) => {
+            snapshot::save_current(&*registry, scope, &options, &clock).unwrap();
+            register::register(
+                &*registry,
+                &*fs,
+                &clock,
+                &speller_roots,
+                scope,
+                &options,
+                &args.tag,
+                &args.path,
+            )
+            .unwrap();
+        }
+        Command::Deregister(args) => {
+            snapshot::save_current(&*registry, scope, &options, &clock).unwrap();
+            deregister::deregister(
+                &*registry,
+                &*fs,
+                &clock,
+                &speller_roots,
+                scope,
+                &options,
+                &args.tag,
+            )
+            .unwrap();
         }
         Command::List(_args) => {
-            list::list();
+            list::list(&*registry, &*fs);
         }
         Command::Nuke(_args) => {
-            crate::reg::nuke_key().unwrap();
-            crate::libreoffice::nuke();
+            snapshot::save_current(&*registry, scope, &options, &clock).unwrap();
+            let mut plan = plan::Plan::new();
+            crate::reg::nuke_key(&*registry, &mut plan, scope, &clock).unwrap();
+            plan.apply(&*registry).unwrap();
+            crate::libreoffice::nuke(&*registry, &*fs, scope, &options.libreoffice_extension_id);
+        }
+        Command::Purge(args) => {
+            snapshot::save_current(&*registry, scope, &options, &clock).unwrap();
+            purge::purge(&*registry, &*fs, &clock, &options, scope, args.force).unwrap();
+        }
+        Command::Plan(args) => {
+            let plan =
+                refresh::plan(&*registry, &*fs, &clock, &speller_roots, scope, &options).unwrap();
+            plan.save(&args.output).unwrap();
+            log::info!("Wrote plan to {}", args.output.display());
+        }
+        Command::Apply(args) => {
+            let plan = plan::Plan::load(&args.path).unwrap();
+            plan.verify(&*registry, &*fs, scope, &options).unwrap();
+            if plan.steps.is_empty() {
+                log::info!("Nothing to apply.");
+            } else {
+                snapshot::save_current(&*registry, scope, &options, &clock).unwrap();
+                plan.apply(&*registry).unwrap();
+            }
+        }
+        Command::Export(args) => {
+            let plan =
+                export::plan(&*registry, &*fs, &clock, &speller_roots, scope, &options).unwrap();
+            export::write(&plan, &args.output).unwrap();
+            log::info!("Wrote registry file to {}", args.output.display());
+        }
+        Command::Diff(args) => {
+            let report = diff::diff(&*registry, &*fs, &speller_roots, scope, &options).unwrap();
+            let text = if args.json {
+                serde_json::to_string_pretty(&report).unwrap()
+            } else {
+                report.to_string()
+            };
+
+            match &args.output {
+                Some(path) => {
+                    std::fs::write(path, text).unwrap();
+                    log::info!("Wrote report to {}", path.display());
+                }
+                None => println!("{}", text),
+            }
+
+            if !report.is_empty() {
+                std::process::exit(1);
+            }
+        }
+        Command::Detect(_args) => {
+            detect::detect(&*registry, &*fs);
+        }
+        Command::Capture(args) => {
+            let capture =
+                capture::Capture::new(&*registry, &*fs, &speller_roots, &options).unwrap();
+            capture.save(&args.output).unwrap();
+            log::info!("Wrote capture to {}", args.output.display());
+        }
+        Command::Snapshots(_args) => {
+            let ids = snapshot::SnapshotStore::new(scope).unwrap().ids().unwrap();
+            println!("Snapshots:");
+            if ids.is_empty() {
+                println!("  - No snapshots stored.");
+            }
+            for id in ids {
+                println!(" - {}", id);
+            }
+        }
+        Command::Restore(args) => {
+            snapshot::restore(
+                &*registry,
+                &clock,
+                &options,
+                scope,
+                args.snapshot.as_deref(),
+            )
+            .unwrap();
         }
+        Command::Config(args) => match args.command {
+            Some(ConfigCommand::Show(_)) => print!("{}", config),
+            None => {
+                eprintln!("Missing required subcommand.");
+                std::process::exit(1);
+            }
+        },
+        Command::Lint(_) | Command::Schema(_) => unreachable!("handled before detection"),
     }
 }
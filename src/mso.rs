@@ -0,0 +1,108 @@
+use std::path::{Path, PathBuf};
+
+use crate::backend::RegistryBackend;
+use crate::filesystem::FileSystem;
+use crate::refresh;
+
+const DLL_NAME: &str = "divvunspellmso.dll";
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum Error {
+    #[error("DivvunSpell MSO DLL not found: {0}")]
+    NotFound(PathBuf),
+}
+
+/// The DivvunSpell MSO DLLs Office loads for every language spelli registers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct MsoDlls {
+    pub(crate) dll32: String,
+    pub(crate) dll64: String,
+}
+
+impl Default for MsoDlls {
+    fn default() -> Self {
+        MsoDlls {
+            dll32: crate::reg::DIVVUNSPELL_MSO_32.to_string(),
+            dll64: crate::reg::DIVVUNSPELL_MSO_64.to_string(),
+        }
+    }
+}
+
+impl MsoDlls {
+    /// The DLLs of a WinDivvun installation in `install_dir`.
+    pub(crate) fn in_dir(install_dir: &Path) -> MsoDlls {
+        MsoDlls {
+            dll32: install_dir
+                .join("i686")
+                .join(DLL_NAME)
+                .to_string_lossy()
+                .to_string(),
+            dll64: install_dir
+                .join("x86_64")
+                .join(DLL_NAME)
+                .to_string_lossy()
+                .to_string(),
+        }
+    }
+
+    /// Finds the DLLs from the WinDivvun Uninstall entry, falling back to
+    /// WinDivvun in Program Files. `dll32` and `dll64` override either one.
+    pub(crate) fn resolve(
+        registry: &dyn RegistryBackend,
+        dll32: Option<&str>,
+        dll64: Option<&str>,
+    ) -> MsoDlls {
+        let mut dlls = match refresh::detect_windivvun(registry) {
+            Some(install_dir) => MsoDlls::in_dir(&install_dir),
+            None => {
+                log::debug!("No WinDivvun Uninstall entry, assuming Program Files");
+                program_files_dlls()
+            }
+        };
+
+        if let Some(dll32) = dll32 {
+            dlls.dll32 = dll32.to_string();
+        }
+        if let Some(dll64) = dll64 {
+            dlls.dll64 = dll64.to_string();
+        }
+
+        log::debug!(
+            "Using DivvunSpell MSO DLLs {} and {}",
+            dlls.dll32,
+            dlls.dll64
+        );
+        dlls
+    }
+
+    /// Checks both DLLs exist, so Office is never pointed at a missing file.
+    pub(crate) fn verify(&self, fs: &dyn FileSystem) -> Result<(), Error> {
+        for dll in [&self.dll32, &self.dll64].iter() {
+            let path = PathBuf::from(dll);
+            if !fs.exists(&path) {
+                return Err(Error::NotFound(path));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether an override's DLL is a DivvunSpell MSO DLL, wherever it was installed.
+pub(crate) fn is_divvunspell_mso(dll: &str) -> bool {
+    dll.rsplit(['\\', '/'])
+        .next()
+        .map(|name| name.eq_ignore_ascii_case(DLL_NAME))
+        .unwrap_or(false)
+}
+
+#[cfg(windows)]
+fn program_files_dlls() -> MsoDlls {
+    let program_files = windirs::known_folder_path(windirs::FolderId::ProgramFiles).unwrap();
+    MsoDlls::in_dir(&program_files.join("WinDivvun"))
+}
+
+#[cfg(not(windows))]
+fn program_files_dlls() -> MsoDlls {
+    MsoDlls::default()
+}
@@ -0,0 +1,734 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use schemars::{
+    gen::{SchemaGenerator, SchemaSettings},
+    schema::{
+        ArrayValidation, InstanceType, Metadata, ObjectValidation, RootSchema, Schema,
+        SchemaObject, SubschemaValidation,
+    },
+    JsonSchema,
+};
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+use crate::filesystem::FileSystem;
+use crate::format::Format;
+use crate::host::Host;
+
+/// The newest speller.toml schema version spelli understands.
+pub(crate) const LATEST_VERSION: u32 = 2;
+
+pub(crate) const SPELLER_TOML: &str = "speller.toml";
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum Error {
+    #[error("An IO error occurred")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid speller.toml")]
+    Parse(#[from] toml::de::Error),
+
+    #[error(
+        "Unsupported speller.toml version {0}, the newest supported is {}",
+        LATEST_VERSION
+    )]
+    UnsupportedVersion(u32),
+
+    #[error("{0} requires speller.toml version 2; add `version = 2`")]
+    RequiresVersion2(String),
+}
+
+/// What a speller package says about itself. Version 1 files have none of it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub(crate) struct PackageMetadata {
+    pub(crate) id: Option<String>,
+    pub(crate) name: Option<String>,
+    pub(crate) version: Option<String>,
+    pub(crate) vendor: Option<String>,
+    #[serde(alias = "licence")]
+    pub(crate) license: Option<String>,
+    pub(crate) description: Option<String>,
+    pub(crate) homepage: Option<String>,
+}
+
+impl PackageMetadata {
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == PackageMetadata::default()
+    }
+}
+
+impl Display for PackageMetadata {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.name, &self.id) {
+            (Some(name), _) => write!(f, "{}", name)?,
+            (None, Some(id)) => write!(f, "{}", id)?,
+            (None, None) => write!(f, "<unnamed>")?,
+        }
+        if let Some(version) = &self.version {
+            write!(f, " {}", version)?;
+        }
+
+        let details = [&self.vendor, &self.license]
+            .iter()
+            .filter_map(|x| x.as_deref())
+            .collect::<Vec<_>>();
+        if !details.is_empty() {
+            write!(f, " ({})", details.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+fn default_version() -> u32 {
+    1
+}
+
+/// A speller package's speller.toml. Files without a `version` are version 1,
+/// which only has the `spellers` table.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub(crate) struct SpellerToml {
+    /// The speller.toml schema version.
+    #[serde(default = "default_version")]
+    pub(crate) version: u32,
+    #[serde(default)]
+    #[schemars(skip_serializing_if = "PackageMetadata::is_empty")]
+    pub(crate) package: PackageMetadata,
+    /// Speller files by the BCP 47 language tag they are registered for.
+    pub(crate) spellers: BTreeMap<String, Speller>,
+}
+
+/// One `[spellers]` entry: either just the speller file, or a table that can
+/// declare its format and SHA-256, or several candidate files in `files`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Speller {
+    /// The files that can provide this tag, most preferred first.
+    pub(crate) candidates: Vec<Candidate>,
+    /// Extra tags, or patterns such as `se-{FI,SE}`, registered for the same
+    /// file exactly as written rather than derived.
+    pub(crate) aliases: Vec<String>,
+    /// The hosts the speller is registered with. All of them unless the table
+    /// lists `hosts`.
+    pub(crate) hosts: Vec<Host>,
+    /// Whether the entry was written as a table, which needs version 2.
+    pub(crate) is_table: bool,
+}
+
+/// One speller file that can provide a tag.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, JsonSchema)]
+pub(crate) struct Candidate {
+    /// The speller file, relative to the package directory.
+    pub(crate) file: String,
+    /// The file's format, if its extension does not say.
+    pub(crate) format: Option<Format>,
+    /// The expected SHA-256 of `file`, in hex.
+    pub(crate) sha256: Option<String>,
+}
+
+impl Candidate {
+    /// The declared format, or the one the file's extension implies.
+    pub(crate) fn format(&self) -> Option<Format> {
+        self.format
+            .or_else(|| Format::from_path(Path::new(&self.file)))
+    }
+}
+
+/// A `[spellers]` entry written as a table, which requires version 2. It has
+/// either `file` or `files`.
+#[derive(Deserialize, JsonSchema)]
+struct SpellerTable {
+    /// The speller file, relative to the package directory.
+    file: Option<String>,
+    /// The format of `file`, if its extension does not say.
+    format: Option<Format>,
+    /// The expected SHA-256 of `file`, in hex.
+    sha256: Option<String>,
+    /// The files that can provide this tag, most preferred first.
+    #[schemars(length(min = 1))]
+    files: Option<Vec<Candidate>>,
+    /// Extra tags, or patterns such as `se-{FI,SE}`, registered for the same file.
+    #[serde(default)]
+    aliases: Vec<String>,
+    /// The hosts the speller is registered with, all of them if missing.
+    #[serde(default)]
+    #[schemars(schema_with = "hosts_schema")]
+    hosts: Option<Vec<Host>>,
+}
+
+impl Speller {
+    /// Every tag the aliases stand for, with patterns expanded.
+    pub(crate) fn alias_tags(&self) -> Result<Vec<String>, String> {
+        let mut out = vec![];
+        for alias in self.aliases.iter() {
+            out.extend(expand_alias(alias)?);
+        }
+        Ok(out)
+    }
+}
+
+/// Expands each `{a,b}` group in an alias pattern, so `se-{FI,SE}` stands for
+/// `se-FI` and `se-SE`. Patterns without groups are a single tag.
+pub(crate) fn expand_alias(pattern: &str) -> Result<Vec<String>, String> {
+    let start = match pattern.find('{') {
+        Some(v) => v,
+        None if pattern.contains('}') => return Err(format!("Unmatched '}}' in '{}'", pattern)),
+        None if pattern.contains('*') => {
+            return Err(format!(
+                "Wildcards are not supported in '{}', list the tags with {{a,b}}",
+                pattern
+            ))
+        }
+        None => return Ok(vec![pattern.to_string()]),
+    };
+    let end = match pattern[start..].find('}') {
+        Some(v) => start + v,
+        None => return Err(format!("Unmatched '{{' in '{}'", pattern)),
+    };
+
+    let group = &pattern[start + 1..end];
+    if group.contains('{') {
+        return Err(format!("Nested '{{' in '{}'", pattern));
+    }
+
+    let mut out = vec![];
+    for alternative in group.split(',') {
+        if alternative.is_empty() {
+            return Err(format!("Empty alternative in '{}'", pattern));
+        }
+        let rest = format!(
+            "{}{}{}",
+            &pattern[..start],
+            alternative,
+            &pattern[end + 1..]
+        );
+        out.extend(expand_alias(&rest)?);
+    }
+    Ok(out)
+}
+
+impl<'de> Deserialize<'de> for Speller {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = Speller;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "a speller file path or a table with `file` or `files`")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Speller, E> {
+                Ok(Speller {
+                    candidates: vec![Candidate {
+                        file: v.to_string(),
+                        format: None,
+                        sha256: None,
+                    }],
+                    aliases: vec![],
+                    hosts: Host::ALL.to_vec(),
+                    is_table: false,
+                })
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, map: A) -> Result<Speller, A::Error> {
+                // Deserializing through the map keeps unknown keys visible to lint.
+                let table = SpellerTable::deserialize(de::value::MapAccessDeserializer::new(map))?;
+                let candidates = match (table.file, table.files) {
+                    (Some(file), None) => vec![Candidate {
+                        file,
+                        format: table.format,
+                        sha256: table.sha256,
+                    }],
+                    (None, Some(files)) if table.format.is_none() && table.sha256.is_none() => {
+                        if files.is_empty() {
+                            return Err(de::Error::custom("`files` must not be empty"));
+                        }
+                        files
+                    }
+                    (None, Some(_)) => {
+                        return Err(de::Error::custom(
+                            "`format` and `sha256` belong in each of `files`",
+                        ))
+                    }
+                    (Some(_), Some(_)) => {
+                        return Err(de::Error::custom("use either `file` or `files`, not both"))
+                    }
+                    (None, None) => return Err(de::Error::missing_field("file")),
+                };
+
+                let mut hosts = table.hosts.unwrap_or_else(|| Host::ALL.to_vec());
+                hosts.sort();
+                hosts.dedup();
+                let readers = Host::SPELLERS_KEY_READERS
+                    .iter()
+                    .filter(|x| hosts.contains(x))
+                    .count();
+                if readers != 0 && readers != Host::SPELLERS_KEY_READERS.len() {
+                    return Err(de::Error::custom(
+                        "`libreoffice` and `windows` read the same spellers, list both or neither",
+                    ));
+                }
+
+                Ok(Speller {
+                    candidates,
+                    aliases: table.aliases,
+                    hosts,
+                    is_table: true,
+                })
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+impl JsonSchema for Speller {
+    fn schema_name() -> String {
+        "Speller".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let mut file = gen.subschema_for::<String>().into_object();
+        file.metadata().description =
+            Some("The speller file, relative to the package directory.".to_string());
+
+        // The same rules as `visit_map`: `file` without `files`, or `files`
+        // without `file`, `format` or `sha256`
+        let table = SchemaObject {
+            subschemas: Some(Box::new(SubschemaValidation {
+                all_of: Some(vec![gen.subschema_for::<SpellerTable>()]),
+                one_of: Some(vec![
+                    SchemaObject {
+                        subschemas: Some(Box::new(SubschemaValidation {
+                            not: Some(Box::new(requires("files"))),
+                            ..Default::default()
+                        })),
+                        ..requires("file").into_object()
+                    }
+                    .into(),
+                    SchemaObject {
+                        subschemas: Some(Box::new(SubschemaValidation {
+                            not: Some(Box::new(
+                                SchemaObject {
+                                    subschemas: Some(Box::new(SubschemaValidation {
+                                        any_of: Some(vec![
+                                            requires("file"),
+                                            requires("format"),
+                                            requires("sha256"),
+                                        ]),
+                                        ..Default::default()
+                                    })),
+                                    ..Default::default()
+                                }
+                                .into(),
+                            )),
+                            ..Default::default()
+                        })),
+                        ..requires("files").into_object()
+                    }
+                    .into(),
+                ]),
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+
+        SchemaObject {
+            metadata: Some(Box::new(Metadata {
+                description: Some(
+                    "A speller file path, or a table with `file` or `files`.".to_string(),
+                ),
+                ..Default::default()
+            })),
+            subschemas: Some(Box::new(SubschemaValidation {
+                any_of: Some(vec![file.into(), table.into()]),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+/// `hosts`, where `libreoffice` and `windows` come together or not at all.
+fn hosts_schema(gen: &mut SchemaGenerator) -> Schema {
+    let contains = |host: Host| -> Schema {
+        SchemaObject {
+            array: Some(Box::new(ArrayValidation {
+                contains: Some(Box::new(
+                    SchemaObject {
+                        const_value: Some(host.to_string().into()),
+                        ..Default::default()
+                    }
+                    .into(),
+                )),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    };
+
+    let mut schema = gen.subschema_for::<Vec<Host>>().into_object();
+    schema.subschemas().if_schema = Some(Box::new(contains(Host::LibreOffice)));
+    schema.subschemas().then_schema = Some(Box::new(contains(Host::Windows)));
+    schema.subschemas().else_schema = Some(Box::new(
+        SchemaObject {
+            subschemas: Some(Box::new(SubschemaValidation {
+                not: Some(Box::new(contains(Host::Windows))),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into(),
+    ));
+    schema.into()
+}
+
+/// A schema matching tables that have `key`.
+fn requires(key: &str) -> Schema {
+    SchemaObject {
+        object: Some(Box::new(ObjectValidation {
+            required: std::iter::once(key.to_string()).collect(),
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+    .into()
+}
+
+impl SpellerToml {
+    /// The JSON Schema of speller.toml, generated from the types refresh reads.
+    pub(crate) fn schema() -> RootSchema {
+        // TOML has no null, optional keys are just left out
+        let mut schema = SchemaSettings::draft07()
+            .with(|x| x.option_add_null_type = false)
+            .into_generator()
+            .into_root_schema_for::<SpellerToml>();
+
+        // The versions `check_version` accepts
+        if let Some(Schema::Object(version)) = schema.schema.object().properties.get_mut("version")
+        {
+            version.number().minimum = Some(1.0);
+            version.number().maximum = Some(f64::from(LATEST_VERSION));
+        }
+
+        // The keys `version_2_keys` rejects in version 1 files
+        let mut v1_file = SchemaObject::default();
+        v1_file.object().properties.insert(
+            "version".to_string(),
+            SchemaObject {
+                const_value: Some(1.into()),
+                ..Default::default()
+            }
+            .into(),
+        );
+        let mut v1_spellers = SchemaObject::default();
+        v1_spellers.object().additional_properties = Some(Box::new(
+            SchemaObject {
+                instance_type: Some(InstanceType::String.into()),
+                ..Default::default()
+            }
+            .into(),
+        ));
+        let mut v1_keys = SchemaObject::default();
+        v1_keys
+            .object()
+            .properties
+            .insert("package".to_string(), Schema::Bool(false));
+        v1_keys
+            .object()
+            .properties
+            .insert("spellers".to_string(), v1_spellers.into());
+        schema.schema.subschemas().if_schema = Some(Box::new(v1_file.into()));
+        schema.schema.subschemas().then_schema = Some(Box::new(v1_keys.into()));
+        schema
+    }
+
+    pub(crate) fn parse(text: &str) -> Result<SpellerToml, Error> {
+        let speller_toml: SpellerToml = toml::from_str(text)?;
+        speller_toml.check_version()?;
+        Ok(speller_toml)
+    }
+
+    pub(crate) fn check_version(&self) -> Result<(), Error> {
+        if self.version == 0 || self.version > LATEST_VERSION {
+            return Err(Error::UnsupportedVersion(self.version));
+        }
+        match self.version_2_keys().into_iter().next() {
+            Some((_, _, e)) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// The keys using what only version 2 allows, as the table and key they are
+    /// written at and the error they cause. Empty for version 2 files.
+    pub(crate) fn version_2_keys(&self) -> Vec<(Option<&'static str>, &str, Error)> {
+        let mut keys = vec![];
+        if self.version >= 2 {
+            return keys;
+        }
+        if !self.package.is_empty() {
+            keys.push((
+                None,
+                "package",
+                Error::RequiresVersion2("[package]".to_string()),
+            ));
+        }
+        for (tag, _) in self.spellers.iter().filter(|(_, x)| x.is_table) {
+            keys.push((
+                Some("spellers"),
+                tag.as_str(),
+                Error::RequiresVersion2(format!("The table entry '{}'", tag)),
+            ));
+        }
+        keys
+    }
+
+    /// Reads the speller.toml of the package in `dir`.
+    pub(crate) fn load(fs: &dyn FileSystem, dir: &Path) -> Result<SpellerToml, Error> {
+        SpellerToml::parse(&fs.read_to_string(&dir.join(SPELLER_TOML))?)
+    }
+}
+
+/// An installed speller package, for reports.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Package {
+    pub(crate) root: PathBuf,
+    pub(crate) dir: PathBuf,
+    /// The speller.toml schema version.
+    pub(crate) version: u32,
+    #[serde(rename = "package")]
+    pub(crate) metadata: PackageMetadata,
+}
+
+impl Display for Package {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.dir.display())?;
+        if !self.metadata.is_empty() {
+            write!(f, ": {}", self.metadata)?;
+        }
+        Ok(())
+    }
+}
+
+/// Every readable speller package in the speller roots, highest precedence first.
+pub(crate) fn packages(fs: &dyn FileSystem, speller_roots: &[PathBuf]) -> Vec<Package> {
+    let mut out = vec![];
+
+    for root in speller_roots.iter() {
+        let mut dirs = match fs.read_dir(root) {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        dirs.sort();
+
+        for dir in dirs.into_iter().filter(|x| fs.is_dir(x)) {
+            if let Ok(speller_toml) = SpellerToml::load(fs, &dir) {
+                out.push(Package {
+                    root: root.clone(),
+                    dir,
+                    version: speller_toml.version,
+                    metadata: speller_toml.package,
+                });
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Files only version 2 allows.
+    const VERSION_2_FILES: &[&str] = &[
+        "[package]\nname = \"North Sami\"\n[spellers]\nse = \"se.zhfst\"\n",
+        "[spellers]\nse = { file = \"se.zhfst\" }\n",
+    ];
+
+    /// `[spellers]` entries the parser accepts.
+    const SPELLERS: &[&str] = &[
+        r#"se = "se.zhfst""#,
+        r#"se = { files = [{ file = "se.bhfst" }, { file = "se.zhfst", sha256 = "00" }], hosts = ["office"] }"#,
+        r#"se = { file = "se.zhfst", hosts = ["windows", "libreoffice"] }"#,
+    ];
+
+    const INVALID_TABLES: &[&str] = &[
+        r#"se = {}"#,
+        r#"se = { file = "se.zhfst", files = [{ file = "se.zhfst" }] }"#,
+        r#"se = { files = [] }"#,
+        r#"se = { files = [{ file = "se.zhfst" }], sha256 = "00" }"#,
+        r#"se = { files = [{ file = "se.zhfst" }], format = "zhfst" }"#,
+    ];
+
+    const INVALID_HOSTS: &[&str] = &[
+        r#"se = { file = "se.zhfst", hosts = ["libreoffice"] }"#,
+        r#"se = { file = "se.zhfst", hosts = ["office", "windows"] }"#,
+    ];
+
+    fn speller(text: &str) -> Result<Speller, Error> {
+        let speller_toml = SpellerToml::parse(&format!("version = 2\n[spellers]\n{}", text))?;
+        Ok(speller_toml.spellers.into_iter().next().unwrap().1)
+    }
+
+    #[test]
+    fn version_defaults_to_1() {
+        let speller_toml = SpellerToml::parse("[spellers]\nse = \"se.zhfst\"\n").unwrap();
+        assert_eq!(speller_toml.version, 1);
+        assert!(speller_toml.package.is_empty());
+    }
+
+    #[test]
+    fn version_2_keys_are_rejected_in_version_1_files() {
+        for text in VERSION_2_FILES.iter() {
+            assert!(
+                matches!(SpellerToml::parse(text), Err(Error::RequiresVersion2(_))),
+                "{} was accepted",
+                text
+            );
+            assert!(SpellerToml::parse(&format!("version = 2\n{}", text)).is_ok());
+        }
+    }
+
+    #[test]
+    fn unsupported_versions_are_rejected() {
+        for version in [0, LATEST_VERSION + 1].iter() {
+            let text = format!("version = {}\n[spellers]\nse = \"se.zhfst\"\n", version);
+            assert!(matches!(
+                SpellerToml::parse(&text),
+                Err(Error::UnsupportedVersion(x)) if x == *version
+            ));
+        }
+    }
+
+    #[test]
+    fn a_path_is_a_single_candidate_for_every_host() {
+        let speller = speller(SPELLERS[0]).unwrap();
+        assert_eq!(speller.candidates.len(), 1);
+        assert_eq!(speller.candidates[0].file, "se.zhfst");
+        assert_eq!(speller.hosts, Host::ALL.to_vec());
+        assert!(!speller.is_table);
+    }
+
+    #[test]
+    fn tables_list_candidates_in_order() {
+        let speller = speller(SPELLERS[1]).unwrap();
+        let files = speller
+            .candidates
+            .iter()
+            .map(|x| x.file.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(files, vec!["se.bhfst", "se.zhfst"]);
+        assert_eq!(speller.candidates[0].format(), Some(Format::Bhfst));
+        assert_eq!(speller.candidates[1].sha256.as_deref(), Some("00"));
+        assert_eq!(speller.hosts, vec![Host::Office]);
+        assert!(speller.is_table);
+    }
+
+    #[test]
+    fn invalid_tables_are_rejected() {
+        for text in INVALID_TABLES.iter() {
+            assert!(speller(text).is_err(), "{} was accepted", text);
+        }
+    }
+
+    #[test]
+    fn libreoffice_and_windows_are_listed_together() {
+        let hosts = speller(SPELLERS[2]).unwrap().hosts;
+        assert_eq!(hosts, vec![Host::LibreOffice, Host::Windows]);
+
+        for text in INVALID_HOSTS.iter() {
+            assert!(speller(text).is_err(), "{} was accepted", text);
+        }
+    }
+
+    #[test]
+    fn aliases_expand_groups() {
+        assert_eq!(expand_alias("se").unwrap(), vec!["se"]);
+        assert_eq!(expand_alias("se-{FI,SE}").unwrap(), vec!["se-FI", "se-SE"]);
+        assert_eq!(
+            expand_alias("{se,sma}-{FI,SE}").unwrap(),
+            vec!["se-FI", "se-SE", "sma-FI", "sma-SE"]
+        );
+    }
+
+    #[test]
+    fn invalid_aliases_are_rejected() {
+        for pattern in ["se-{FI", "se-FI}", "se-{}", "se-{{FI}}", "se-*"].iter() {
+            assert!(expand_alias(pattern).is_err(), "{} was accepted", pattern);
+        }
+    }
+
+    #[test]
+    fn schema_has_the_rules_of_the_parser() {
+        let schema = serde_json::to_value(SpellerToml::schema()).unwrap();
+
+        let version = &schema["properties"]["version"];
+        assert_eq!(version["minimum"], 1.0);
+        assert_eq!(version["maximum"], f64::from(LATEST_VERSION));
+
+        let v1_file = &schema["then"]["properties"];
+        assert_eq!(schema["if"]["properties"]["version"]["const"], 1);
+        assert_eq!(v1_file["package"], false);
+        assert_eq!(
+            v1_file["spellers"]["additionalProperties"]["type"],
+            "string"
+        );
+
+        let table = &schema["definitions"]["SpellerTable"];
+        assert_eq!(table["properties"]["files"]["minItems"], 1);
+
+        let one_of = &schema["definitions"]["Speller"]["anyOf"][1]["oneOf"];
+        assert_eq!(one_of[0]["required"], serde_json::json!(["file"]));
+        assert_eq!(one_of[0]["not"]["required"], serde_json::json!(["files"]));
+        assert_eq!(one_of[1]["required"], serde_json::json!(["files"]));
+        assert_eq!(
+            one_of[1]["not"]["anyOf"],
+            serde_json::json!([
+                { "required": ["file"] },
+                { "required": ["format"] },
+                { "required": ["sha256"] }
+            ])
+        );
+    }
+
+    #[test]
+    fn the_schema_accepts_what_the_parser_accepts() {
+        let schema = serde_json::to_value(SpellerToml::schema()).unwrap();
+        let schema = jsonschema::JSONSchema::compile(&schema).unwrap();
+
+        let mut files = vec![];
+        for text in VERSION_2_FILES.iter() {
+            files.push(text.to_string());
+            files.push(format!("version = 2\n{}", text));
+        }
+        for text in SPELLERS
+            .iter()
+            .chain(INVALID_TABLES.iter())
+            .chain(INVALID_HOSTS.iter())
+        {
+            files.push(format!("version = 2\n[spellers]\n{}\n", text));
+        }
+        for version in 0..=LATEST_VERSION + 1 {
+            files.push(format!(
+                "version = {}\n[spellers]\nse = \"se.zhfst\"\n",
+                version
+            ));
+        }
+
+        for text in files.iter() {
+            let value: toml::Value = toml::from_str(text).unwrap();
+            let value = serde_json::to_value(value).unwrap();
+            assert_eq!(
+                schema.is_valid(&value),
+                SpellerToml::parse(text).is_ok(),
+                "the schema and the parser disagree on {}",
+                text
+            );
+        }
+    }
+}
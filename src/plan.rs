@@ -0,0 +1,743 @@
+use std::{
+    fmt::Display,
+    fs::File,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::backend::{Hive, KeyPath, RegistryBackend, Value, View};
+use crate::filesystem::FileSystem;
+use crate::journal::Journal;
+use crate::libreoffice;
+use crate::refresh::{self, RefreshOptions};
+use crate::reg::{self, Scope};
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum Error {
+    #[error("A registry error occurred")]
+    Registry(#[from] reg::Error),
+
+    #[error("An IO error occurred")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid plan file")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Could not start unopkg at {0}")]
+    UnopkgStart(PathBuf, #[source] std::io::Error),
+
+    #[error("unopkg {args} exited with {status}")]
+    Unopkg {
+        args: String,
+        status: std::process::ExitStatus,
+    },
+
+    #[error("Refusing step {index} ({step}): {reason}")]
+    Refused {
+        index: usize,
+        step: String,
+        reason: String,
+    },
+
+    #[error("Step {index} ({step}) failed; {undone} changes were rolled back")]
+    RolledBack {
+        index: usize,
+        step: String,
+        undone: usize,
+        #[source]
+        source: Box<Error>,
+    },
+
+    #[error("{0} cannot be undone")]
+    Irreversible(String),
+
+    #[error(
+        "Step {index} ({step}) failed and {} of {undone} changes could not be rolled back: {}",
+        .failed.len(),
+        .failed.join("; ")
+    )]
+    RollbackFailed {
+        index: usize,
+        step: String,
+        undone: usize,
+        /// The undos that failed or were not possible.
+        failed: Vec<String>,
+        #[source]
+        source: Box<Error>,
+    },
+}
+
+/// Where the bytes of a `WriteFile` step come from. Payloads are embedded in
+/// spelli itself so plans stay small and reviewable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Contents {
+    LibreofficeExtension,
+}
+
+impl Contents {
+    fn data(&self) -> &'static [u8] {
+        match self {
+            Contents::LibreofficeExtension => libreoffice::OXT_DATA,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub(crate) enum Step {
+    CreateKey {
+        key: KeyPath,
+    },
+    DeleteKey {
+        key: KeyPath,
+    },
+    SetValue {
+        key: KeyPath,
+        name: String,
+        value: Value,
+    },
+    DeleteValue {
+        key: KeyPath,
+        name: String,
+    },
+    /// Sets a counter such as Count to `value`, or past the stored value if
+    /// that is already as high, so applying an old plan never lowers it.
+    SetCounter {
+        key: KeyPath,
+        name: String,
+        value: u32,
+    },
+    CreateDir {
+        path: PathBuf,
+    },
+    WriteFile {
+        path: PathBuf,
+        contents: Contents,
+    },
+    RemoveFile {
+        path: PathBuf,
+    },
+    Unopkg {
+        unopkg: PathBuf,
+        args: Vec<String>,
+        /// A failing exit status is expected, e.g. when removing an extension that is not installed.
+        allow_failure: bool,
+    },
+}
+
+impl Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Step::CreateKey { key } => write!(f, "Create key {}", key),
+            Step::DeleteKey { key } => write!(f, "Delete key {}", key),
+            Step::SetValue { key, name, value } => {
+                write!(f, "Set '{}' -> '{}' in {}", name, value, key)
+            }
+            Step::DeleteValue { key, name } => write!(f, "Delete '{}' in {}", name, key),
+            Step::SetCounter { key, name, value } => {
+                write!(f, "Set '{}' -> '{}' or higher in {}", name, value, key)
+            }
+            Step::CreateDir { path } => write!(f, "Create directory {}", path.display()),
+            Step::WriteFile { path, contents } => {
+                write!(f, "Write {:?} to {}", contents, path.display())
+            }
+            Step::RemoveFile { path } => write!(f, "Remove file {}", path.display()),
+            Step::Unopkg { unopkg, args, .. } => {
+                write!(f, "Run {} {}", unopkg.display(), args.join(" "))
+            }
+        }
+    }
+}
+
+/// Every change spelli is about to make to a machine, in order. Building a plan
+/// only reads from the system; `apply` is what performs the changes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Plan {
+    pub(crate) steps: Vec<Step>,
+}
+
+impl Plan {
+    pub(crate) fn new() -> Plan {
+        Default::default()
+    }
+
+    pub(crate) fn create_key(&mut self, key: KeyPath) {
+        self.steps.push(Step::CreateKey { key });
+    }
+
+    pub(crate) fn delete_key(&mut self, key: KeyPath) {
+        self.steps.push(Step::DeleteKey { key });
+    }
+
+    pub(crate) fn set_value(&mut self, key: &KeyPath, name: &str, value: Value) {
+        self.steps.push(Step::SetValue {
+            key: key.clone(),
+            name: name.to_string(),
+            value,
+        });
+    }
+
+    pub(crate) fn delete_value(&mut self, key: &KeyPath, name: &str) {
+        self.steps.push(Step::DeleteValue {
+            key: key.clone(),
+            name: name.to_string(),
+        });
+    }
+
+    pub(crate) fn set_counter(&mut self, key: &KeyPath, name: &str, value: u32) {
+        self.steps.push(Step::SetCounter {
+            key: key.clone(),
+            name: name.to_string(),
+            value,
+        });
+    }
+
+    pub(crate) fn push(&mut self, step: Step) {
+        self.steps.push(step);
+    }
+
+    pub(crate) fn load(path: &Path) -> Result<Plan, Error> {
+        Ok(serde_json::from_reader(File::open(path)?)?)
+    }
+
+    pub(crate) fn save(&self, path: &Path) -> Result<(), Error> {
+        serde_json::to_writer_pretty(File::create(path)?, self)?;
+        Ok(())
+    }
+
+    /// Checks that every step only touches what spelli itself manages in
+    /// `scope`, so a plan from elsewhere cannot be used to write arbitrary
+    /// keys or files, or run arbitrary programs.
+    pub(crate) fn verify(
+        &self,
+        registry: &dyn RegistryBackend,
+        fs: &dyn FileSystem,
+        scope: Scope,
+        options: &RefreshOptions,
+    ) -> Result<(), Error> {
+        let owned = Owned::new(registry, fs, scope, options);
+
+        for (index, step) in self.steps.iter().enumerate() {
+            if let Err(reason) = owned.check(step) {
+                return Err(Error::Refused {
+                    index,
+                    step: step.to_string(),
+                    reason,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies every step in order. If a step fails, the steps already applied
+    /// are undone in reverse order, leaving the machine as it was beforehand.
+    /// Steps that could not be undone are named in `RollbackFailed`.
+    pub(crate) fn apply(&self, registry: &dyn RegistryBackend) -> Result<(), Error> {
+        log::info!("Applying plan with {} steps", self.steps.len());
+        let mut journal = Journal::new();
+
+        for (index, step) in self.steps.iter().enumerate() {
+            log::debug!("{}", step);
+            let result = Journal::undo(registry, step).and_then(|undo| {
+                apply_step(registry, step)?;
+                journal.record(undo);
+                Ok(())
+            });
+
+            if let Err(e) = result {
+                log::error!("Step {} failed: {}: {:?}", index, step, e);
+                log::warn!("Rolling back {} changes", journal.len());
+
+                let undone = journal.len();
+                let failed = journal.rollback(registry);
+                let step = step.to_string();
+                let source = Box::new(e);
+
+                if !failed.is_empty() {
+                    log::error!(
+                        "Rollback incomplete: {} of {} undo steps failed",
+                        failed.len(),
+                        undone
+                    );
+                    return Err(Error::RollbackFailed {
+                        index,
+                        step,
+                        undone,
+                        failed,
+                        source,
+                    });
+                }
+
+                log::warn!("Rollback complete.");
+                return Err(Error::RolledBack {
+                    index,
+                    step,
+                    undone,
+                    source,
+                });
+            }
+        }
+
+        log::info!("Plan applied.");
+        Ok(())
+    }
+}
+
+/// What a plan may touch in one scope.
+struct Owned {
+    /// Keys that may be changed along with everything below them.
+    trees: Vec<KeyPath>,
+    /// Keys whose subkeys may be changed, but not the key itself.
+    parents: Vec<KeyPath>,
+    /// The key holding the SpellersUpdated marker, and nothing else of ours.
+    marker: KeyPath,
+    /// The only unopkg that may run, and the commands it may run.
+    unopkg: Option<PathBuf>,
+    unopkg_args: Vec<Vec<String>>,
+    install_dir: PathBuf,
+}
+
+/// Whether `key` is `root` or below it, in the registry view both end up in.
+fn is_within(key: &KeyPath, root: &KeyPath) -> bool {
+    let segments = key.segments();
+    let root_segments = root.segments();
+    key.hive == root.hive
+        && segments.len() >= root_segments.len()
+        && segments
+            .iter()
+            .zip(root_segments.iter())
+            .all(|(a, b)| a.eq_ignore_ascii_case(b))
+}
+
+impl Owned {
+    fn new(
+        registry: &dyn RegistryBackend,
+        fs: &dyn FileSystem,
+        scope: Scope,
+        options: &RefreshOptions,
+    ) -> Owned {
+        let mut trees = vec![reg::spellers_key(scope), reg::manual_spellers_key(scope)];
+        let mut parents = vec![];
+        match scope {
+            Scope::Machine => trees.extend(
+                refresh::all_user_settings_paths(options)
+                    .into_iter()
+                    .map(|path| KeyPath::new(Hive::LocalMachine, View::Registry64, path)),
+            ),
+            Scope::User => parents.push(reg::user_overrides_key(options)),
+        }
+
+        let install_dir = libreoffice::get_speller_install_directory(scope);
+        let oxt = libreoffice::oxt_path(scope);
+        Owned {
+            trees,
+            parents,
+            marker: reg::windivvun_key(scope),
+            unopkg: libreoffice::find_unopkg(registry, fs),
+            unopkg_args: vec![
+                libreoffice::unopkg_args("remove", &options.libreoffice_extension_id, scope),
+                libreoffice::unopkg_args("add", &oxt.to_string_lossy(), scope),
+            ],
+            install_dir,
+        }
+    }
+
+    fn check_key(&self, key: &KeyPath) -> Result<(), String> {
+        let owned = self.trees.iter().any(|root| is_within(key, root))
+            || self
+                .parents
+                .iter()
+                .any(|root| is_within(key, root) && key.segments().len() > root.segments().len());
+        if owned {
+            Ok(())
+        } else {
+            Err(format!("{} is not a key spelli manages", key))
+        }
+    }
+
+    fn check_path(&self, path: &Path) -> Result<(), String> {
+        let escapes = path
+            .components()
+            .any(|x| x == std::path::Component::ParentDir);
+        if path.starts_with(&self.install_dir) && !escapes {
+            Ok(())
+        } else {
+            Err(format!(
+                "{} is outside {}",
+                path.display(),
+                self.install_dir.display()
+            ))
+        }
+    }
+
+    fn check(&self, step: &Step) -> Result<(), String> {
+        match step {
+            Step::CreateKey { key } | Step::DeleteKey { key } => self.check_key(key),
+            Step::SetValue { key, name, .. }
+            | Step::SetCounter { key, name, .. }
+            | Step::DeleteValue { key, name } => {
+                let is_marker = name.eq_ignore_ascii_case(reg::SPELLERS_UPDATED)
+                    && is_within(key, &self.marker)
+                    && key.segments().len() == self.marker.segments().len();
+                if is_marker {
+                    Ok(())
+                } else {
+                    self.check_key(key)
+                }
+            }
+            Step::CreateDir { path } | Step::WriteFile { path, .. } | Step::RemoveFile { path } => {
+                self.check_path(path)
+            }
+            Step::Unopkg { unopkg, args, .. } => match &self.unopkg {
+                Some(expected) if expected != unopkg => Err(format!(
+                    "{} is not the unopkg of the installed LibreOffice, {}",
+                    unopkg.display(),
+                    expected.display()
+                )),
+                None => Err("LibreOffice is not installed".to_string()),
+                Some(_) if !self.unopkg_args.contains(args) => {
+                    Err(format!("`{}` is not a command spelli runs", args.join(" ")))
+                }
+                Some(_) => Ok(()),
+            },
+        }
+    }
+}
+
+fn apply_step(registry: &dyn RegistryBackend, step: &Step) -> Result<(), Error> {
+    match step {
+        Step::CreateKey { key } => registry.create_key(key)?,
+        Step::DeleteKey { key } => registry.delete_key(key)?,
+        Step::SetValue { key, name, value } => registry.set_value(key, name, value)?,
+        Step::DeleteValue { key, name } => registry.delete_value(key, name)?,
+        Step::SetCounter { key, name, value } => {
+            let value = match registry.value(key, name)? {
+                Some(Value::U32(stored)) if stored >= *value => {
+                    log::warn!("'{}' in {} is already {}", name, key, stored);
+                    stored.saturating_add(1)
+                }
+                _ => *value,
+            };
+            registry.set_value(key, name, &Value::U32(value))?
+        }
+        Step::CreateDir { path } => std::fs::create_dir_all(path)?,
+        Step::WriteFile { path, contents } => std::fs::write(path, contents.data())?,
+        Step::RemoveFile { path } => std::fs::remove_file(path)?,
+        Step::Unopkg {
+            unopkg,
+            args,
+            allow_failure,
+        } => run_unopkg(unopkg, args, *allow_failure)?,
+    }
+
+    Ok(())
+}
+
+/// Runs unopkg, failing if it cannot start or, unless `allow_failure`, exits
+/// with a failing status.
+pub(crate) fn run_unopkg(unopkg: &Path, args: &[String], allow_failure: bool) -> Result<(), Error> {
+    let output = Command::new(unopkg)
+        .args(args)
+        .output()
+        .map_err(|e| Error::UnopkgStart(unopkg.to_path_buf(), e))?;
+    log::debug!("Unopkg exited with status: {}", output.status);
+    if !output.status.success() && !allow_failure {
+        log::error!("Unopkg failed: {}", args.join(" "));
+        log::error!("stdout: {}", &String::from_utf8_lossy(&output.stdout));
+        log::error!("stderr: {}", &String::from_utf8_lossy(&output.stderr));
+        return Err(Error::Unopkg {
+            args: args.join(" "),
+            status: output.status,
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryRegistry;
+    use crate::testing::{self, FixedClock};
+
+    fn key(path: &str) -> KeyPath {
+        KeyPath::new(Hive::LocalMachine, View::Registry64, path)
+    }
+
+    #[test]
+    fn a_failing_step_rolls_back_the_steps_before_it() {
+        let registry = MemoryRegistry::new();
+        registry
+            .set_value(&key(r"SOFTWARE\A"), "x", &Value::U32(1))
+            .unwrap();
+
+        let dir = testing::temp_dir("rollback");
+        let file = dir.join("file");
+        std::fs::write(&file, b"").unwrap();
+
+        let mut plan = Plan::new();
+        plan.set_value(&key(r"SOFTWARE\A"), "x", Value::U32(2));
+        plan.set_value(&key(r"SOFTWARE\A"), "y", Value::U32(3));
+        plan.create_key(key(r"SOFTWARE\B\C"));
+        plan.delete_key(key(r"SOFTWARE\A"));
+        // A directory cannot be created where a file is
+        plan.push(Step::CreateDir { path: file });
+
+        match plan.apply(&registry) {
+            Err(Error::RolledBack { index, undone, .. }) => {
+                assert_eq!(index, 4);
+                assert_eq!(undone, 4);
+            }
+            other => panic!("expected a rollback, got {:?}", other),
+        }
+
+        assert_eq!(
+            registry.value(&key(r"SOFTWARE\A"), "x").unwrap(),
+            Some(Value::U32(1))
+        );
+        assert_eq!(registry.value(&key(r"SOFTWARE\A"), "y").unwrap(), None);
+        assert!(!registry.key_exists(&key(r"SOFTWARE\B")).unwrap());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn refused(plan: &Plan, scope: Scope) -> Option<usize> {
+        let (registry, fs) = testing::machine();
+        match plan.verify(&registry, &fs, scope, &RefreshOptions::default()) {
+            Ok(()) => None,
+            Err(Error::Refused { index, .. }) => Some(index),
+            Err(e) => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn refresh_plans_verify() {
+        let (registry, mut fs) = testing::machine();
+        testing::add_package(
+            &mut fs,
+            "se",
+            "[spellers]\nse = \"se.zhfst\"\n",
+            &["se.zhfst"],
+        );
+        let options = RefreshOptions::default();
+
+        for scope in [Scope::Machine, Scope::User].iter() {
+            let plan = refresh::plan(
+                &registry,
+                &fs,
+                &FixedClock::at(1000),
+                &testing::roots(),
+                *scope,
+                &options,
+            )
+            .unwrap();
+            assert!(!plan.steps.is_empty());
+            plan.verify(&registry, &fs, *scope, &options).unwrap();
+        }
+    }
+
+    #[test]
+    fn keys_spelli_does_not_manage_are_refused() {
+        let run = key(r"SOFTWARE\Microsoft\Windows\CurrentVersion\Run");
+        let mut plan = Plan::new();
+        plan.set_value(&reg::spellers_key(Scope::Machine), "se", Value::None);
+        plan.set_value(&run, "x", Value::String("x.exe".to_string()));
+        assert_eq!(refused(&plan, Scope::Machine), Some(1));
+
+        // The other scope's keys are not this scope's to change
+        let mut plan = Plan::new();
+        plan.delete_key(reg::spellers_key(Scope::User));
+        assert_eq!(refused(&plan, Scope::Machine), Some(0));
+
+        // Only the marker may be set on the WinDivvun key itself
+        let mut plan = Plan::new();
+        let windivvun = reg::windivvun_key(Scope::Machine);
+        plan.set_value(&windivvun, reg::SPELLERS_UPDATED, Value::U32(1));
+        plan.set_value(&windivvun, "InstallPath", Value::String("x".to_string()));
+        assert_eq!(refused(&plan, Scope::Machine), Some(1));
+
+        // Per-user overrides, but not the key holding every vendor's overrides
+        let options = RefreshOptions::default();
+        let overrides = reg::user_overrides_key(&options);
+        let mut plan = Plan::new();
+        plan.delete_key(overrides.join("se"));
+        plan.delete_key(overrides);
+        assert_eq!(refused(&plan, Scope::User), Some(1));
+    }
+
+    #[test]
+    fn files_outside_the_extension_directory_are_refused() {
+        let dir = libreoffice::get_speller_install_directory(Scope::Machine);
+        let mut plan = Plan::new();
+        plan.push(Step::CreateDir { path: dir.clone() });
+        plan.push(Step::WriteFile {
+            path: dir.join("divvunspell.oxt"),
+            contents: Contents::LibreofficeExtension,
+        });
+        plan.push(Step::WriteFile {
+            path: dir.join("..").join("evil.dll"),
+            contents: Contents::LibreofficeExtension,
+        });
+        assert_eq!(refused(&plan, Scope::Machine), Some(2));
+    }
+
+    #[test]
+    fn unopkg_is_refused_without_libreoffice() {
+        let mut plan = Plan::new();
+        plan.push(Step::Unopkg {
+            unopkg: PathBuf::from("cmd.exe"),
+            args: vec!["/c".to_string()],
+            allow_failure: false,
+        });
+        assert_eq!(refused(&plan, Scope::Machine), Some(0));
+    }
+
+    #[test]
+    fn only_the_installed_unopkg_runs_and_only_our_commands() {
+        let (registry, mut fs) = testing::machine();
+        let unopkg = testing::add_libreoffice(&registry, &mut fs);
+        let options = RefreshOptions::default();
+        let remove =
+            libreoffice::unopkg_args("remove", &options.libreoffice_extension_id, Scope::Machine);
+        let verify = |plan: &Plan| plan.verify(&registry, &fs, Scope::Machine, &options);
+
+        let mut plan = Plan::new();
+        plan.push(Step::Unopkg {
+            unopkg: unopkg.clone(),
+            args: remove.clone(),
+            allow_failure: true,
+        });
+        verify(&plan).unwrap();
+
+        plan.push(Step::Unopkg {
+            unopkg: PathBuf::from("cmd.exe"),
+            args: remove,
+            allow_failure: true,
+        });
+        plan.push(Step::Unopkg {
+            unopkg,
+            args: vec![
+                "add".to_string(),
+                "--shared".to_string(),
+                r"C:\evil.oxt".to_string(),
+            ],
+            allow_failure: false,
+        });
+        assert!(matches!(
+            verify(&plan),
+            Err(Error::Refused { index: 1, .. })
+        ));
+        plan.steps.remove(1);
+        assert!(matches!(
+            verify(&plan),
+            Err(Error::Refused { index: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn a_failed_file_step_leaves_nothing_to_undo() {
+        let registry = MemoryRegistry::new();
+        let dir = testing::temp_dir("failed-write");
+        let created = dir.join("created");
+
+        let mut plan = Plan::new();
+        plan.set_value(&key(r"SOFTWARE\A"), "x", Value::U32(1));
+        plan.push(Step::CreateDir {
+            path: created.clone(),
+        });
+        plan.push(Step::WriteFile {
+            path: dir.join("missing").join("divvunspell.oxt"),
+            contents: Contents::LibreofficeExtension,
+        });
+
+        match plan.apply(&registry) {
+            Err(Error::RolledBack { index, undone, .. }) => {
+                assert_eq!(index, 2);
+                assert_eq!(undone, 2);
+            }
+            other => panic!("expected a rollback, got {:?}", other),
+        }
+
+        assert!(!registry.key_exists(&key(r"SOFTWARE\A")).unwrap());
+        assert!(!created.exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn unopkg(unopkg: &str, allow_failure: bool) -> Result<(), Error> {
+        let mut plan = Plan::new();
+        plan.push(Step::Unopkg {
+            unopkg: PathBuf::from(unopkg),
+            args: vec![],
+            allow_failure,
+        });
+        plan.apply(&MemoryRegistry::new())
+    }
+
+    #[test]
+    fn unopkg_that_cannot_start_fails_the_plan() {
+        match unopkg("/nonexistent/unopkg.com", true) {
+            Err(Error::RolledBack { source, .. }) => {
+                assert!(matches!(*source, Error::UnopkgStart(..)))
+            }
+            other => panic!("expected a rollback, got {:?}", other),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unopkg_failures_fail_the_plan_unless_allowed() {
+        match unopkg("false", false) {
+            Err(Error::RolledBack { source, .. }) => {
+                assert!(matches!(*source, Error::Unopkg { .. }))
+            }
+            other => panic!("expected a rollback, got {:?}", other),
+        }
+        unopkg("false", true).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rolling_back_an_irreversible_unopkg_fails_the_rollback() {
+        let dir = testing::temp_dir("irreversible-unopkg");
+        let options = RefreshOptions::default();
+        let mut plan = Plan::new();
+        plan.push(Step::Unopkg {
+            unopkg: PathBuf::from("true"),
+            args: libreoffice::unopkg_args("add", "divvunspell.oxt", Scope::Machine),
+            allow_failure: false,
+        });
+        plan.push(Step::Unopkg {
+            unopkg: PathBuf::from("true"),
+            args: libreoffice::unopkg_args(
+                "remove",
+                &options.libreoffice_extension_id,
+                Scope::User,
+            ),
+            allow_failure: true,
+        });
+        plan.push(Step::WriteFile {
+            path: dir.join("missing").join("divvunspell.oxt"),
+            contents: Contents::LibreofficeExtension,
+        });
+
+        match plan.apply(&MemoryRegistry::new()) {
+            Err(Error::RollbackFailed {
+                index,
+                undone,
+                failed,
+                ..
+            }) => {
+                assert_eq!(index, 2);
+                assert_eq!(undone, 2);
+                assert_eq!(failed.len(), 2);
+                assert!(failed[0].contains("remove"), "{:?}", failed);
+                assert!(failed[1].contains("add"), "{:?}", failed);
+            }
+            other => panic!("expected a failed rollback, got {:?}", other),
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,329 @@
+use crate::backend::{Hive, KeyPath, RegistryBackend, Value, View};
+use crate::clock::Clock;
+use crate::filesystem::FileSystem;
+use crate::libreoffice;
+use crate::plan::{self, Plan};
+use crate::refresh::{self, RefreshOptions};
+use crate::reg::{self, Langs, Scope};
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum Error {
+    #[error("A registry error occurred")]
+    Registry(#[from] reg::Error),
+
+    #[error("Failed to apply plan")]
+    Plan(#[from] plan::Error),
+}
+
+/// Where Office records the Count it last processed for a User Settings path,
+/// in the profile of the user running it. Click-to-Run and 32-bit Office use
+/// the same per-user location as a native MSI install.
+fn processed_count_key(base_path: &str) -> Option<KeyPath> {
+    let lower = base_path.to_lowercase();
+    let start = lower.rfind(r"microsoft\office\")?;
+    Some(KeyPath::new(
+        Hive::CurrentUser,
+        View::Default,
+        format!(r"SOFTWARE\{}", &base_path[start..]),
+    ))
+}
+
+fn count(registry: &dyn RegistryBackend, key: &KeyPath) -> Result<Option<u32>, reg::Error> {
+    match registry.value(key, "Count")? {
+        Some(Value::U32(x)) => Ok(Some(x)),
+        _ => Ok(None),
+    }
+}
+
+/// Removes the WinDivvun key itself if nothing but spelli's own data is in it.
+fn delete_windivvun_key_if_unused(
+    registry: &dyn RegistryBackend,
+    plan: &mut Plan,
+    scope: Scope,
+) -> Result<(), reg::Error> {
+    let key = reg::windivvun_key(scope);
+    if !registry.key_exists(&key)? {
+        return Ok(());
+    }
+
+    let owned = [reg::spellers_key(scope), reg::manual_spellers_key(scope)];
+    let other_keys = registry
+        .subkeys(&key)?
+        .into_iter()
+        .filter(|name| !owned.contains(&key.join(name)))
+        .count();
+    let other_values = registry
+        .values(&key)?
+        .into_iter()
+        .filter(|(name, _)| !name.eq_ignore_ascii_case(reg::SPELLERS_UPDATED))
+        .count();
+
+    if other_keys == 0 && other_values == 0 {
+        plan.delete_key(key);
+    } else {
+        log::info!("Keeping {} as it holds other data", key);
+        plan.delete_value(&key, reg::SPELLERS_UPDATED);
+    }
+
+    Ok(())
+}
+
+/// Plans marking every registered language as deleted in every User Settings
+/// path, so Office removes the overrides it has copied into user profiles.
+fn plan_tombstones(
+    registry: &dyn RegistryBackend,
+    plan: &mut Plan,
+    langs: &mut Langs,
+    options: &RefreshOptions,
+    clock: &dyn Clock,
+) -> Result<(), reg::Error> {
+    let live = langs.create.keys().cloned().collect::<Vec<_>>();
+    langs.deregister(plan, &live, clock);
+    let spellers_updated = reg::mark_spellers_updated(registry, plan, Scope::Machine, clock)?;
+
+    for path in refresh::user_settings_paths(registry, options) {
+        langs.refresh(
+            registry,
+            plan,
+            &path,
+            Some(spellers_updated),
+            options,
+            clock,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// User Settings paths of installed Office versions whose Delete records the
+/// current user's Office has not processed yet. Paths of Office versions that
+/// are not installed will never be processed, so they are not waited for.
+/// Other users' Counts live in their own profiles and cannot be checked, so
+/// Office only removes the overrides of users who start it before the purge.
+fn unprocessed_paths(
+    registry: &dyn RegistryBackend,
+    options: &RefreshOptions,
+) -> Result<Vec<String>, reg::Error> {
+    let mut out = vec![];
+
+    for path in refresh::installed_user_settings_paths(registry, options) {
+        let base = KeyPath::new(Hive::LocalMachine, View::Registry64, &path);
+        let written = match count(registry, &base)? {
+            Some(x) => x,
+            None => continue,
+        };
+
+        let processed = match processed_count_key(&path) {
+            Some(key) => count(registry, &key)?,
+            None => None,
+        };
+
+        if processed.map(|x| x < written).unwrap_or(true) {
+            out.push(path);
+        }
+    }
+
+    Ok(out)
+}
+
+fn purge_machine(
+    registry: &dyn RegistryBackend,
+    fs: &dyn FileSystem,
+    clock: &dyn Clock,
+    options: &RefreshOptions,
+    force: bool,
+) -> Result<bool, Error> {
+    let mut langs = Langs::new(registry, Scope::Machine)?;
+
+    if !langs.create.is_empty() && !force {
+        let mut plan = Plan::new();
+        plan_tombstones(registry, &mut plan, &mut langs, options, clock)?;
+        plan.apply(registry)?;
+
+        log::warn!(
+            "Marked {} languages as deleted. Start Office so it can remove them, then run purge again.",
+            langs.delete.len()
+        );
+        return Ok(false);
+    }
+
+    let unprocessed = unprocessed_paths(registry, options)?;
+    if !unprocessed.is_empty() && !force {
+        for path in unprocessed.iter() {
+            log::warn!(
+                "Office has not processed the Delete records in {} yet",
+                path
+            );
+        }
+        log::warn!("Start Office so it can remove them, or purge with --force.");
+        log::warn!(
+            "Only the Office of the user running purge is checked. Other users keep their copies unless they start Office first."
+        );
+        return Ok(false);
+    }
+
+    let mut plan = Plan::new();
+
+    for path in refresh::all_user_settings_paths(options) {
+        let base = KeyPath::new(Hive::LocalMachine, View::Registry64, &path);
+        if registry.key_exists(&base)? {
+            plan.delete_key(base);
+        }
+
+        // Office's record of what it processed for the user running purge
+        if let Some(key) = processed_count_key(&path) {
+            if registry.key_exists(&key)? {
+                plan.delete_key(key);
+            }
+        }
+    }
+
+    plan.delete_key(reg::spellers_key(Scope::Machine));
+    plan.delete_key(reg::manual_spellers_key(Scope::Machine));
+    delete_windivvun_key_if_unused(registry, &mut plan, Scope::Machine)?;
+    plan.apply(registry)?;
+
+    libreoffice::nuke(
+        registry,
+        fs,
+        Scope::Machine,
+        &options.libreoffice_extension_id,
+    );
+    Ok(true)
+}
+
+fn purge_user(
+    registry: &dyn RegistryBackend,
+    fs: &dyn FileSystem,
+    options: &RefreshOptions,
+) -> Result<bool, Error> {
+    // Per-user overrides are read directly by Office, so they can go right away
+    let mut langs = Langs::new(registry, Scope::User)?;
+    let live = std::mem::take(&mut langs.create);
+    langs.delete.extend(live.into_keys());
+
+    let mut plan = Plan::new();
+    langs.refresh_user_overrides(registry, &mut plan, options)?;
+    plan.delete_key(reg::spellers_key(Scope::User));
+    plan.delete_key(reg::manual_spellers_key(Scope::User));
+    delete_windivvun_key_if_unused(registry, &mut plan, Scope::User)?;
+    plan.apply(registry)?;
+
+    libreoffice::nuke(registry, fs, Scope::User, &options.libreoffice_extension_id);
+    Ok(true)
+}
+
+/// Removes every trace of WinDivvun from the registry. For the machine scope,
+/// registered languages are first marked as deleted and purge stops until
+/// Office has processed the Delete records, unless `force` is set. Only the
+/// current user's Office is waited for. Returns whether everything was removed.
+pub(crate) fn purge(
+    registry: &dyn RegistryBackend,
+    fs: &dyn FileSystem,
+    clock: &dyn Clock,
+    options: &RefreshOptions,
+    scope: Scope,
+    force: bool,
+) -> Result<bool, Error> {
+    log::info!("Purging {} registrations", scope);
+
+    let purged = match scope {
+        Scope::Machine => purge_machine(registry, fs, clock, options, force)?,
+        Scope::User => purge_user(registry, fs, options)?,
+    };
+
+    if purged {
+        log::info!("Purge complete.");
+    }
+
+    Ok(purged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryRegistry;
+    use crate::filesystem::MemoryFileSystem;
+    use crate::testing::{self, FixedClock};
+
+    const SE: &str = "[spellers]\nse = \"se.zhfst\"\n";
+
+    const BASE_PATH: &str = r"SOFTWARE\Microsoft\Office\16.0\User Settings\WinDivvun";
+
+    /// A machine with Office and one package registered by a refresh.
+    fn refreshed(clock: &FixedClock) -> (MemoryRegistry, MemoryFileSystem) {
+        let (registry, mut fs) = testing::machine();
+        testing::add_package(&mut fs, "se", SE, &["se.zhfst"]);
+        testing::add_office(&registry);
+        refresh::plan(
+            &registry,
+            &fs,
+            clock,
+            &testing::roots(),
+            Scope::Machine,
+            &RefreshOptions::default(),
+        )
+        .unwrap()
+        .apply(&registry)
+        .unwrap();
+        (registry, fs)
+    }
+
+    fn is_purged(registry: &MemoryRegistry) -> bool {
+        let base = KeyPath::new(Hive::LocalMachine, View::Registry64, BASE_PATH);
+        !registry.key_exists(&base).unwrap()
+            && !registry
+                .key_exists(&reg::spellers_key(Scope::Machine))
+                .unwrap()
+    }
+
+    #[test]
+    fn purge_waits_for_office_to_process_the_delete_records() {
+        let clock = FixedClock::at(1000);
+        let (registry, fs) = refreshed(&clock);
+        let options = RefreshOptions::default();
+
+        clock.set(2000);
+        assert!(!purge(&registry, &fs, &clock, &options, Scope::Machine, false).unwrap());
+        let record = reg::delete_records_key(BASE_PATH, &options).join("se");
+        assert!(registry.key_exists(&record).unwrap());
+
+        // Office has not started yet
+        assert!(!purge(&registry, &fs, &clock, &options, Scope::Machine, false).unwrap());
+        assert!(!is_purged(&registry));
+
+        let processed = processed_count_key(BASE_PATH).unwrap();
+        registry
+            .set_value(&processed, "Count", &Value::U32(2000))
+            .unwrap();
+        assert!(purge(&registry, &fs, &clock, &options, Scope::Machine, false).unwrap());
+        assert!(is_purged(&registry));
+        assert!(!registry.key_exists(&processed).unwrap());
+    }
+
+    #[test]
+    fn force_purges_at_once() {
+        let clock = FixedClock::at(1000);
+        let (registry, fs) = refreshed(&clock);
+        let options = RefreshOptions::default();
+
+        assert!(purge(&registry, &fs, &clock, &options, Scope::Machine, true).unwrap());
+        assert!(is_purged(&registry));
+    }
+
+    #[test]
+    fn office_that_is_not_installed_is_not_waited_for() {
+        let clock = FixedClock::at(1000);
+        let (registry, fs) = refreshed(&clock);
+        let options = RefreshOptions::default();
+        let uninstall = KeyPath::new(Hive::LocalMachine, View::Registry64, refresh::KEY_UNINSTALL);
+        registry
+            .delete_key(&uninstall.join("Office16.PROPLUS"))
+            .unwrap();
+
+        clock.set(2000);
+        assert!(!purge(&registry, &fs, &clock, &options, Scope::Machine, false).unwrap());
+        assert!(purge(&registry, &fs, &clock, &options, Scope::Machine, false).unwrap());
+        assert!(is_purged(&registry));
+    }
+}
@@ -1,18 +1,21 @@
-use crate::{libreoffice, reg, register};
-use registry::{Data, Hive, RegKey, Security};
-use serde::Deserialize;
+use crate::backend::{Hive, KeyPath, RegistryBackend, Value, View};
+use crate::clock::Clock;
+use crate::filesystem::FileSystem;
+use crate::format::{self, Format};
+use crate::host::Host;
+use crate::mso::{self, MsoDlls};
+use crate::package::{Speller, SpellerToml};
+use crate::plan::{self, Contents, Plan, Step};
+use crate::reg::{Origin, Scope, Source};
+use crate::{checksum, libreoffice, reg, register, snapshot};
 use std::{
-    collections::BTreeMap, fmt::Display, fs::File, io::Read, path::PathBuf, process::Command,
+    collections::BTreeMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+    time::Duration,
 };
 use unic_langid::LanguageIdentifier;
 
-const OXT_DATA: &[u8] = include_bytes!("../divvunspell-libreoffice.oxt");
-
-#[derive(Debug, Clone, Deserialize)]
-struct SpellerToml {
-    spellers: BTreeMap<String, String>,
-}
-
 #[derive(Debug, thiserror::Error)]
 pub(crate) enum Error {
     #[error("A registry error occurred")]
@@ -23,63 +26,264 @@ pub(crate) enum Error {
 
     #[error("Invalid language tag")]
     InvalidLanguageTag(#[from] unic_langid::LanguageIdentifierError),
+
+    #[error("Failed to apply plan")]
+    Plan(#[from] plan::Error),
+
+    #[error("Cannot register spellers with Office")]
+    MsoDll(#[from] mso::Error),
+
+    #[error("Failed to snapshot the registry")]
+    Snapshot(#[from] snapshot::Error),
+}
+
+/// How long Delete records of deregistered tags are kept by default.
+pub(crate) const DEFAULT_TOMBSTONE_RETENTION_DAYS: u64 = 30;
+
+/// Settings that change what a refresh does.
+#[derive(Debug, Clone)]
+pub(crate) struct RefreshOptions {
+    /// How long a deregistered tag keeps its tombstone and Delete records.
+    pub(crate) tombstone_retention: Duration,
+    /// The DLLs written into every Office record.
+    pub(crate) mso_dlls: MsoDlls,
+    /// The speller formats the installed DivvunSpell engine can load.
+    pub(crate) speller_formats: Vec<Format>,
+    /// Where Office looks for proofing tool overrides, relative to a User
+    /// Settings path or HKCU.
+    pub(crate) proof_tool_path: String,
+    /// The name of the key spelli owns under each Office User Settings key.
+    pub(crate) user_settings_name: String,
+    /// The identifier of the LibreOffice extension, for unopkg.
+    pub(crate) libreoffice_extension_id: String,
+}
+
+impl Default for RefreshOptions {
+    fn default() -> Self {
+        RefreshOptions {
+            tombstone_retention: Duration::from_secs(DEFAULT_TOMBSTONE_RETENTION_DAYS * 86400),
+            mso_dlls: MsoDlls::default(),
+            speller_formats: vec![Format::Zhfst],
+            proof_tool_path: reg::BASE_PROOF_TOOL_PATH.to_string(),
+            user_settings_name: reg::USER_SETTINGS_NAME.to_string(),
+            libreoffice_extension_id: libreoffice::EXTENSION_ID.to_string(),
+        }
+    }
 }
 
-pub(crate) fn refresh() -> Result<(), Error> {
+pub(crate) fn refresh(
+    registry: &dyn RegistryBackend,
+    fs: &dyn FileSystem,
+    clock: &dyn Clock,
+    speller_roots: &[PathBuf],
+    scope: Scope,
+    options: &RefreshOptions,
+) -> Result<(), Error> {
     log::info!("Beginning refresh process");
 
-    // Try to read the spellers directory before we blindly delete everything
-    let speller_dirs = std::fs::read_dir(reg::SPELLER_DIR)?;
-    let speller_tomls: Vec<(PathBuf, SpellerToml)> = speller_dirs
-        .into_iter()
-        .filter_map(|x| match x {
-            Ok(v) if v.metadata().map(|m| m.is_dir()).unwrap_or(false) => Some(v.path()),
-            _ => None,
-        })
-        .filter_map(|path| {
-            let p = path.join("speller.toml");
-            match File::open(&p) {
-                Ok(v) => Some((path, v)),
-                Err(e) => {
-                    log::error!("Error loading speller.toml at path: {}", path.display());
-                    log::error!("{:?}", e);
-                    None
-                }
+    let plan = plan(registry, fs, clock, speller_roots, scope, options)?;
+    if plan.steps.is_empty() {
+        log::info!("Nothing to change.");
+        return Ok(());
+    }
+
+    snapshot::save_current(registry, scope, options, clock)?;
+    plan.apply(registry)?;
+
+    log::info!("Refresh completed.");
+    Ok(())
+}
+
+/// Language tags derived from one speller.toml entry, and the speller file they point to.
+#[derive(Debug, Clone)]
+pub(crate) struct Registration {
+    pub(crate) tags: Vec<String>,
+    pub(crate) path: PathBuf,
+    pub(crate) source: Source,
+    /// The tag the entry was declared for, which `tags` were derived from.
+    pub(crate) entry: String,
+    pub(crate) format: Format,
+    /// The tags in `tags` that are declared aliases rather than derived.
+    pub(crate) aliases: Vec<String>,
+    /// The hosts the speller is registered with.
+    pub(crate) hosts: Vec<Host>,
+}
+
+impl Registration {
+    pub(crate) fn origin(&self, tag: &str) -> Origin {
+        let origin = if self.aliases.iter().any(|x| x == tag) {
+            Origin::alias(self.source.clone(), &self.entry, tag, self.format)
+        } else {
+            Origin::new(self.source.clone(), &self.entry, tag, self.format)
+        };
+        Origin {
+            hosts: self.hosts.clone(),
+            ..origin
+        }
+    }
+
+    pub(crate) fn is_for(&self, host: Host) -> bool {
+        self.hosts.contains(&host)
+    }
+}
+
+/// The canonical tags of `speller`'s aliases that `tags` does not already have.
+/// Invalid aliases are skipped.
+fn alias_tags(tag: &str, speller: &Speller, tags: &[String]) -> Vec<String> {
+    let patterns = match speller.alias_tags() {
+        Ok(v) => v,
+        Err(e) => {
+            log::error!("Ignoring the aliases of '{}': {}", tag, e);
+            return vec![];
+        }
+    };
+
+    let mut out: Vec<String> = vec![];
+    for alias in patterns {
+        let alias = match alias.parse::<LanguageIdentifier>() {
+            Ok(v) => v.to_string(),
+            Err(e) => {
+                log::error!("Invalid alias `{}` of '{}'", alias, tag);
+                log::error!("{:?}", e);
+                continue;
             }
-        })
-        .filter_map(|(path, mut file)| {
-            let mut s = String::new();
-            match file.read_to_string(&mut s) {
-                Ok(_) => {}
-                Err(e) => {
-                    log::error!("Error reading `{}`", path.display());
-                    log::error!("{:?}", e);
-                    return None;
-                }
+        };
+
+        if tags
+            .iter()
+            .chain(out.iter())
+            .any(|x| x.eq_ignore_ascii_case(&alias))
+        {
+            log::debug!("Alias '{}' of '{}' is already registered", alias, tag);
+            continue;
+        }
+        log::info!("Adding alias '{}' of '{}'", alias, tag);
+        out.push(alias);
+    }
+    out
+}
+
+/// Reads every speller.toml in the speller roots and derives the language tags
+/// each speller should be registered for. Registrations come lowest precedence
+/// first, so later ones win. Roots and packages that cannot be read are skipped.
+pub(crate) fn registrations(
+    fs: &dyn FileSystem,
+    speller_roots: &[PathBuf],
+    formats: &[Format],
+) -> Result<Vec<Registration>, Error> {
+    let mut out = vec![];
+
+    for root in speller_roots.iter().rev() {
+        let mut speller_dirs = match fs.read_dir(root) {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("Error reading speller root: {}", root.display());
+                log::error!("{:?}", e);
+                continue;
             }
-            match toml::from_str(&s) {
-                Ok(x) => Some((path, x)),
-                Err(e) => {
-                    log::error!("Error parsing `{}`", path.display());
-                    log::error!("{:?}", e);
-                    None
-                }
+        };
+        speller_dirs.sort();
+        out.extend(root_registrations(fs, root, speller_dirs, formats)?);
+    }
+
+    Ok(out)
+}
+
+/// The first of `speller`'s candidates in a format the engine supports that is
+/// a valid speller file with the declared SHA-256.
+fn choose_candidate(
+    fs: &dyn FileSystem,
+    dir: &Path,
+    tag: &str,
+    speller: &Speller,
+    formats: &[Format],
+) -> Option<(PathBuf, Format)> {
+    for candidate in speller.candidates.iter() {
+        let format = match candidate.format() {
+            Some(v) => v,
+            None => {
+                log::error!(
+                    "Unknown format of '{}' for '{}', declare its `format`",
+                    candidate.file,
+                    tag
+                );
+                continue;
+            }
+        };
+        if !formats.contains(&format) {
+            log::info!(
+                "Skipping '{}' for '{}', DivvunSpell cannot load {}",
+                candidate.file,
+                tag,
+                format
+            );
+            continue;
+        }
+
+        let path = dir.join(&candidate.file);
+        if let Err(e) = format::validate(fs, &path, format) {
+            log::error!("Not using '{}' for '{}': {}", candidate.file, tag, e);
+            log::error!("{:?}", e);
+            continue;
+        }
+        if let Some(sha256) = &candidate.sha256 {
+            if let Err(e) = checksum::verify(fs, &path, sha256) {
+                log::error!("Not using '{}' for '{}': {}", candidate.file, tag, e);
+                log::error!("{:?}", e);
+                continue;
+            }
+        }
+
+        return Some((path, format));
+    }
+
+    None
+}
+
+fn root_registrations(
+    fs: &dyn FileSystem,
+    root: &Path,
+    speller_dirs: Vec<PathBuf>,
+    formats: &[Format],
+) -> Result<Vec<Registration>, Error> {
+    let speller_tomls: Vec<(PathBuf, SpellerToml)> = speller_dirs
+        .into_iter()
+        .filter(|path| fs.is_dir(path))
+        .filter_map(|path| match SpellerToml::load(fs, &path) {
+            Ok(x) => Some((path, x)),
+            Err(e) => {
+                log::error!("Error loading speller.toml at path: {}", path.display());
+                log::error!("{:?}", e);
+                None
             }
         })
         .collect::<Vec<_>>();
 
-    // Remove all currently added languages
-    reg::nuke_key()?;
+    let mut out = vec![];
 
-    // Add languages that exist with a valid toml file
     for (toml_path, speller_toml) in speller_tomls {
-        log::info!("Reading {}...", toml_path.display());
+        if speller_toml.package.is_empty() {
+            log::info!("Reading {}...", toml_path.display());
+        } else {
+            log::info!(
+                "Reading {} ({})...",
+                toml_path.display(),
+                speller_toml.package
+            );
+        }
 
-        for (tag, path) in speller_toml.spellers.iter() {
-            let lang_id: LanguageIdentifier = tag.parse()?;
+        for (tag, speller) in speller_toml.spellers.iter() {
+            let lang_id: LanguageIdentifier = match tag.parse() {
+                Ok(v) => v,
+                Err(e) => {
+                    log::error!("Invalid language tag `{}` in {}", tag, toml_path.display());
+                    log::error!("{:?}", e);
+                    continue;
+                }
+            };
             log::info!("Registering speller for '{}'...", &lang_id);
 
-            let keys = match register::derive_lang_id_keys(lang_id) {
+            let mut tags = match register::derive_lang_id_keys(lang_id.clone()) {
                 Ok(v) => v,
                 Err(e) => {
                     log::error!("Error deriving language keys for `{}`", tag);
@@ -87,53 +291,316 @@ pub(crate) fn refresh() -> Result<(), Error> {
                     continue;
                 }
             };
+            let aliases = alias_tags(tag, speller, &tags);
+            tags.extend(aliases.iter().cloned());
 
-            crate::reg::register_langs(&keys, &toml_path.join(path))?;
+            let (path, format) = match choose_candidate(fs, &toml_path, tag, speller, formats) {
+                Some(v) => v,
+                None => {
+                    log::error!("Not registering '{}': no usable speller file", tag);
+                    continue;
+                }
+            };
+
+            out.push(Registration {
+                tags,
+                path,
+                source: Source::Package {
+                    root: root.to_path_buf(),
+                    dir: toml_path.clone(),
+                },
+                entry: lang_id.to_string(),
+                format,
+                aliases,
+                hosts: speller.hosts.clone(),
+            });
         }
     }
 
-    // Iterate relevant registry key for all lang-id -> zhfst path value pairs
-    log::info!("Detecting MS Office installations...");
-    let _unused = detect_ms_office();
+    Ok(out)
+}
+
+/// The WinDivvun key under one of Office's User Settings keys.
+fn windivvun_user_settings(user_settings: &str, options: &RefreshOptions) -> String {
+    format!(r"{}\{}", user_settings, options.user_settings_name)
+}
+
+/// Every Office User Settings path a refresh writes to on this machine.
+pub(crate) fn user_settings_paths(
+    registry: &dyn RegistryBackend,
+    options: &RefreshOptions,
+) -> Vec<String> {
+    Office::all_supported()
+        .iter()
+        .filter_map(|x| x.user_settings_paths(registry))
+        .flatten()
+        .map(|x| windivvun_user_settings(x, options))
+        .collect()
+}
 
-    let offices = Office::all_supported();
-    // if offices.is_empty() {
-    //     log::warn!("No Office installations detected; aborting.");
-    //     return Ok(());
-    // }
+/// The speller packages' registrations followed by the manual ones, so manual
+/// registrations win. Tags deregistered manually are left out entirely.
+pub(crate) fn desired_registrations(
+    fs: &dyn FileSystem,
+    speller_roots: &[PathBuf],
+    manual: &BTreeMap<String, Option<String>>,
+    formats: &[Format],
+) -> Result<Vec<Registration>, Error> {
+    let mut out = registrations(fs, speller_roots, formats)?;
+    let mut suppressed = vec![];
 
-    let langs = reg::Langs::new().unwrap();
-    for paths in offices.iter().flat_map(|x| x.user_settings_paths()) {
-        for path in paths {
-            match langs.refresh(path) {
-                Ok(_) => {
-                    log::info!("Refreshed reg keys for {}", &path);
+    for (tag, path) in manual.iter() {
+        let lang_id: LanguageIdentifier = match tag.parse() {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("Invalid manually registered language tag `{}`", tag);
+                log::error!("{:?}", e);
+                continue;
+            }
+        };
+        let tags = match register::derive_lang_id_keys(lang_id.clone()) {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("Error deriving language keys for `{}`", tag);
+                log::error!("{:?}", e);
+                continue;
+            }
+        };
+
+        match path {
+            Some(path) => {
+                let format = Format::from_path(Path::new(path)).unwrap_or(Format::Zhfst);
+                if !formats.contains(&format) {
+                    log::error!(
+                        "Not registering '{}': DivvunSpell cannot load {}",
+                        tag,
+                        format
+                    );
+                    continue;
                 }
-                Err(err) => {
-                    log::error!("Error for {}: {:?}", &path, err);
+                if let Err(e) = format::validate(fs, Path::new(path), format) {
+                    log::error!("Not registering '{}': {}", tag, e);
+                    log::error!("{:?}", e);
+                    continue;
                 }
+
+                log::info!("Using manual registration for '{}'", tag);
+                out.push(Registration {
+                    tags,
+                    path: PathBuf::from(path),
+                    source: Source::Manual,
+                    entry: lang_id.to_string(),
+                    format,
+                    aliases: vec![],
+                    hosts: Host::ALL.to_vec(),
+                });
+            }
+            None => {
+                log::info!("'{}' was deregistered manually", tag);
+                suppressed.extend(tags);
             }
         }
     }
 
-    refresh_libreoffice_spellchecker();
+    for registration in out.iter_mut() {
+        registration.tags.retain(|tag| !suppressed.contains(tag));
+    }
 
-    log::info!("Refresh completed.");
+    Ok(out)
+}
+
+/// The User Settings paths of the Office versions actually installed on this machine.
+pub(crate) fn installed_user_settings_paths(
+    registry: &dyn RegistryBackend,
+    options: &RefreshOptions,
+) -> Vec<String> {
+    detect_ms_office(registry)
+        .iter()
+        .filter_map(|x| x.user_settings_paths(registry))
+        .flatten()
+        .map(|x| windivvun_user_settings(x, options))
+        .collect()
+}
+
+/// Computes everything a refresh would change without changing anything.
+pub(crate) fn plan(
+    registry: &dyn RegistryBackend,
+    fs: &dyn FileSystem,
+    clock: &dyn Clock,
+    speller_roots: &[PathBuf],
+    scope: Scope,
+    options: &RefreshOptions,
+) -> Result<Plan, Error> {
+    let manual = reg::manual_registrations(registry, scope)?;
+    let mut plan = Plan::new();
+    plan_with(
+        registry,
+        fs,
+        clock,
+        speller_roots,
+        scope,
+        &manual,
+        options,
+        &mut plan,
+    )?;
+    Ok(plan)
+}
+
+/// Adds the steps of a refresh to `plan`, using `manual` in place of the manual
+/// registrations in the registry, so they can be changed in the same plan.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn plan_with(
+    registry: &dyn RegistryBackend,
+    fs: &dyn FileSystem,
+    clock: &dyn Clock,
+    speller_roots: &[PathBuf],
+    scope: Scope,
+    manual: &BTreeMap<String, Option<String>>,
+    options: &RefreshOptions,
+    plan: &mut Plan,
+) -> Result<(), Error> {
+    log::info!("Planning {} refresh", scope);
+
+    let registrations = desired_registrations(fs, speller_roots, manual, &options.speller_formats)?;
+
+    // Later packages win when two of them provide the same tag
+    let mut desired: BTreeMap<String, (String, Origin)> = BTreeMap::new();
+    for registration in registrations.iter() {
+        let path = registration.path.to_string_lossy().to_string();
+        for tag in registration.tags.iter() {
+            let origin = registration.origin(tag);
+            if let Some((_, previous)) = desired.get(tag) {
+                if previous.source != origin.source {
+                    log::warn!("'{}' from {} overrides {}", tag, &origin, previous);
+                }
+            }
+            desired.insert(tag.clone(), (path.clone(), origin));
+        }
+    }
+
+    // Only touch the Spellers values that differ from what the packages want
+    let mut langs = reg::Langs::new(registry, scope)?;
+    let mut spellers_changed = false;
+
+    let stale = langs
+        .create
+        .keys()
+        .filter(|tag| !desired.contains_key(*tag))
+        .cloned()
+        .collect::<Vec<_>>();
+    if !stale.is_empty() {
+        langs.deregister(plan, &stale, clock);
+        spellers_changed = true;
+    }
+
+    for registration in registrations.iter() {
+        let path = registration.path.to_string_lossy().to_string();
+        let mut tags = vec![];
+
+        for tag in registration.tags.iter() {
+            let origin = registration.origin(tag);
+            if desired.get(tag) != Some(&(path.clone(), origin.clone())) {
+                continue;
+            }
+
+            if langs.create.get(tag) != Some(&path)
+                || langs.hidden.contains(tag) == origin.is_in_spellers_key()
+            {
+                tags.push(tag.clone());
+            } else if langs.origins.get(tag) != Some(&origin) {
+                // Office only cares about the path, so this does not count as a change
+                langs.set_origin(plan, tag, origin);
+            }
+        }
+
+        if !tags.is_empty() {
+            langs.register(plan, &tags, &registration.path, &|tag| {
+                registration.origin(tag)
+            });
+            spellers_changed = true;
+        }
+    }
+
+    let expired = langs.collect_tombstones(plan, options.tombstone_retention, clock);
+
+    // Never point Office at a DLL that is not there
+    if langs.create_for(Host::Office).next().is_some() {
+        options.mso_dlls.verify(fs)?;
+    }
+
+    let spellers_updated = if spellers_changed {
+        Some(reg::mark_spellers_updated(registry, plan, scope, clock)?)
+    } else {
+        log::info!("Registered spellers are up to date.");
+        reg::spellers_updated(registry, scope)?
+    };
+
+    match scope {
+        Scope::Machine => {
+            // Iterate relevant registry key for all lang-id -> zhfst path value pairs
+            log::info!("Detecting MS Office installations...");
+            let _unused = detect_ms_office(registry);
+
+            for path in user_settings_paths(registry, options) {
+                langs.drop_delete_records(registry, plan, &path, &expired, options)?;
+                if langs.refresh(registry, plan, &path, spellers_updated, options, clock)? {
+                    log::info!("Planned reg keys for {}", &path);
+                } else {
+                    log::info!("Reg keys for {} are up to date", &path);
+                }
+            }
+        }
+        Scope::User => {
+            let key = reg::user_overrides_key(options);
+            if langs.refresh_user_overrides(registry, plan, options)? {
+                log::info!("Planned reg keys for {}", key);
+            } else {
+                log::info!("Reg keys for {} are up to date", key);
+            }
+        }
+    }
+
+    if langs.create_for(Host::LibreOffice).next().is_some() {
+        refresh_libreoffice_spellchecker(registry, fs, plan, scope, options);
+    } else {
+        log::info!(
+            "No speller is for LibreOffice, removing the LibreOffice spellchecker extension"
+        );
+        remove_libreoffice_spellchecker(registry, fs, plan, scope, options);
+    }
+
+    log::info!("Planned {} steps.", plan.steps.len());
     Ok(())
 }
 
-const KEY_UNINSTALL: &str = r"SOFTWARE\Microsoft\Windows\CurrentVersion\Uninstall";
+pub(crate) const KEY_UNINSTALL: &str = r"SOFTWARE\Microsoft\Windows\CurrentVersion\Uninstall";
 
 pub struct LibreOffice {
     pub install_path: PathBuf,
 }
 
 #[derive(Debug)]
-struct Office {
+pub(crate) struct Office {
     variant: InstallMethod,
     major_version: u32,
 }
 
+impl Display for Office {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Office {} ({:?})", self.major_version, self.variant)
+    }
+}
+
+/// Every Office User Settings path spelli may write to, whichever Office versions are installed.
+pub(crate) fn all_user_settings_paths(options: &RefreshOptions) -> Vec<String> {
+    Office::all_supported()
+        .iter()
+        .filter_map(|x| x.user_settings_paths_wow64())
+        .flatten()
+        .map(|x| windivvun_user_settings(x, options))
+        .collect()
+}
+
 impl Office {
     fn all_supported() -> Vec<Office> {
         let mut out = vec![];
@@ -154,33 +621,33 @@ impl Office {
         out
     }
 
-    fn user_settings_paths_wow64(&self) -> Option<&[&str]> {
+    fn user_settings_paths_wow64(&self) -> Option<&'static [&'static str]> {
         log::debug!("Getting user settings path for 64-bit Windows installation...");
 
         match (&self.variant, self.major_version) {
             (InstallMethod::Msi, 14) => Some(&[
-                r"SOFTWARE\Microsoft\Office\14.0\User Settings\WinDivvun",
-                r"SOFTWARE\Wow6432Node\Microsoft\Office\14.0\User Settings\WinDivvun",
+                r"SOFTWARE\Microsoft\Office\14.0\User Settings",
+                r"SOFTWARE\Wow6432Node\Microsoft\Office\14.0\User Settings",
             ]),
             (InstallMethod::Click2Run, 14) => Some(&[
-                r"SOFTWARE\Microsoft\Office\ClickToRun\REGISTRY\MACHINE\Software\Microsoft\Office\14.0\User Settings\WinDivvun",
-                r"SOFTWARE\Microsoft\Office\ClickToRun\REGISTRY\MACHINE\Software\Wow6432Node\Microsoft\Office\14.0\User Settings\WinDivvun",
+                r"SOFTWARE\Microsoft\Office\ClickToRun\REGISTRY\MACHINE\Software\Microsoft\Office\14.0\User Settings",
+                r"SOFTWARE\Microsoft\Office\ClickToRun\REGISTRY\MACHINE\Software\Wow6432Node\Microsoft\Office\14.0\User Settings",
             ]),
             (InstallMethod::Msi, 15) => Some(&[
-                r"SOFTWARE\Microsoft\Office\15.0\User Settings\WinDivvun",
-                r"SOFTWARE\Wow6432Node\Microsoft\Office\15.0\User Settings\WinDivvun",
+                r"SOFTWARE\Microsoft\Office\15.0\User Settings",
+                r"SOFTWARE\Wow6432Node\Microsoft\Office\15.0\User Settings",
             ]),
             (InstallMethod::Click2Run, 15) => Some(&[
-                r"SOFTWARE\Microsoft\Office\ClickToRun\REGISTRY\MACHINE\Software\Microsoft\Office\15.0\User Settings\WinDivvun",
-                r"SOFTWARE\Microsoft\Office\ClickToRun\REGISTRY\MACHINE\Software\Wow6432Node\Microsoft\Office\15.0\User Settings\WinDivvun",
+                r"SOFTWARE\Microsoft\Office\ClickToRun\REGISTRY\MACHINE\Software\Microsoft\Office\15.0\User Settings",
+                r"SOFTWARE\Microsoft\Office\ClickToRun\REGISTRY\MACHINE\Software\Wow6432Node\Microsoft\Office\15.0\User Settings",
             ]),
             (InstallMethod::Msi, 16) => Some(&[
-                r"SOFTWARE\Microsoft\Office\16.0\User Settings\WinDivvun",
-                r"SOFTWARE\Wow6432Node\Microsoft\Office\16.0\User Settings\WinDivvun",
+                r"SOFTWARE\Microsoft\Office\16.0\User Settings",
+                r"SOFTWARE\Wow6432Node\Microsoft\Office\16.0\User Settings",
             ]),
             (InstallMethod::Click2Run, 16) => Some(&[
-                r"SOFTWARE\Microsoft\Office\ClickToRun\REGISTRY\MACHINE\Software\Microsoft\Office\16.0\User Settings\WinDivvun",
-                r"SOFTWARE\Microsoft\Office\ClickToRun\REGISTRY\MACHINE\Software\Wow6432Node\Microsoft\Office\16.0\User Settings\WinDivvun",
+                r"SOFTWARE\Microsoft\Office\ClickToRun\REGISTRY\MACHINE\Software\Microsoft\Office\16.0\User Settings",
+                r"SOFTWARE\Microsoft\Office\ClickToRun\REGISTRY\MACHINE\Software\Wow6432Node\Microsoft\Office\16.0\User Settings",
             ]),
             _ => {
                 log::error!(
@@ -193,35 +660,30 @@ impl Office {
         }
     }
 
-    fn user_settings_paths(&self) -> Option<&[&str]> {
+    fn user_settings_paths(
+        &self,
+        registry: &dyn RegistryBackend,
+    ) -> Option<&'static [&'static str]> {
         // Detect if the OS has WOW64 support the worst possible way
-        if Hive::LocalMachine
-            .open(r"SOFTWARE\Wow6432Node", Security::Read)
-            .is_ok()
-        {
+        let wow64 = KeyPath::new(Hive::LocalMachine, View::Default, r"SOFTWARE\Wow6432Node");
+        if registry.key_exists(&wow64).unwrap_or(false) {
             return self.user_settings_paths_wow64();
         }
 
         log::debug!("Getting user settings path for 32-bit (or 64-bit missing WOW64) Windows installation...");
 
         match (&self.variant, self.major_version) {
-            (InstallMethod::Msi, 14) => {
-                Some(&[r"SOFTWARE\Microsoft\Office\14.0\User Settings\WinDivvun"])
-            }
+            (InstallMethod::Msi, 14) => Some(&[r"SOFTWARE\Microsoft\Office\14.0\User Settings"]),
             (InstallMethod::Click2Run, 14) => Some(&[
-                r"SOFTWARE\Microsoft\Office\ClickToRun\REGISTRY\MACHINE\Software\Microsoft\Office\14.0\User Settings\WinDivvun",
+                r"SOFTWARE\Microsoft\Office\ClickToRun\REGISTRY\MACHINE\Software\Microsoft\Office\14.0\User Settings",
             ]),
-            (InstallMethod::Msi, 15) => {
-                Some(&[r"SOFTWARE\Microsoft\Office\15.0\User Settings\WinDivvun"])
-            }
+            (InstallMethod::Msi, 15) => Some(&[r"SOFTWARE\Microsoft\Office\15.0\User Settings"]),
             (InstallMethod::Click2Run, 15) => Some(&[
-                r"SOFTWARE\Microsoft\Office\ClickToRun\REGISTRY\MACHINE\Software\Microsoft\Office\15.0\User Settings\WinDivvun",
+                r"SOFTWARE\Microsoft\Office\ClickToRun\REGISTRY\MACHINE\Software\Microsoft\Office\15.0\User Settings",
             ]),
-            (InstallMethod::Msi, 16) => {
-                Some(&[r"SOFTWARE\Microsoft\Office\16.0\User Settings\WinDivvun"])
-            }
+            (InstallMethod::Msi, 16) => Some(&[r"SOFTWARE\Microsoft\Office\16.0\User Settings"]),
             (InstallMethod::Click2Run, 16) => Some(&[
-                r"SOFTWARE\Microsoft\Office\ClickToRun\REGISTRY\MACHINE\Software\Microsoft\Office\16.0\User Settings\WinDivvun",
+                r"SOFTWARE\Microsoft\Office\ClickToRun\REGISTRY\MACHINE\Software\Microsoft\Office\16.0\User Settings",
             ]),
             _ => {
                 log::error!(
@@ -237,11 +699,11 @@ impl Office {
 
 #[derive(Debug, Clone)]
 pub struct CandidateRegKey {
-    publisher: Option<Data>,
-    display_name: Option<Data>,
-    display_version: Option<Data>,
-    install_location: Option<Data>,
-    click_to_run_component: Option<Data>,
+    publisher: Option<Value>,
+    display_name: Option<Value>,
+    display_version: Option<Value>,
+    install_location: Option<Value>,
+    click_to_run_component: Option<Value>,
 }
 
 impl Display for CandidateRegKey {
@@ -271,25 +733,23 @@ impl Display for CandidateRegKey {
     }
 }
 
-impl From<&RegKey> for CandidateRegKey {
-    fn from(regkey: &RegKey) -> Self {
-        let publisher = regkey.value("Publisher").ok();
-        let display_name = regkey.value("DisplayName").ok();
-        let display_version = regkey.value("DisplayVersion").ok();
-        let install_location = regkey.value("InstallLocation").ok();
-        let click_to_run_component = regkey.value("ClickToRunComponent").ok();
+impl CandidateRegKey {
+    fn open(registry: &dyn RegistryBackend, key: &KeyPath) -> Result<Self, reg::Error> {
+        let publisher = registry.value(key, "Publisher")?;
+        let display_name = registry.value(key, "DisplayName")?;
+        let display_version = registry.value(key, "DisplayVersion")?;
+        let install_location = registry.value(key, "InstallLocation")?;
+        let click_to_run_component = registry.value(key, "ClickToRunComponent")?;
 
-        Self {
+        Ok(Self {
             publisher,
             display_name,
             display_version,
             install_location,
             click_to_run_component,
-        }
+        })
     }
-}
 
-impl CandidateRegKey {
     fn validate_office(&self) -> Option<Office> {
         if self.publisher.as_ref()?.to_string() != "Microsoft Corporation" {
             return None;
@@ -305,11 +765,7 @@ impl CandidateRegKey {
         }
 
         let major_version: u32 = match self.display_version.as_ref() {
-            Some(Data::String(s)) => s
-                .to_string_lossy()
-                .split(".")
-                .next()
-                .and_then(|x| x.parse::<u32>().ok())?,
+            Some(Value::String(s)) => s.split('.').next().and_then(|x| x.parse::<u32>().ok())?,
             _ => return None,
         };
 
@@ -325,6 +781,21 @@ impl CandidateRegKey {
         })
     }
 
+    fn validate_windivvun(&self) -> Option<PathBuf> {
+        if !self
+            .display_name
+            .as_ref()?
+            .to_string()
+            .starts_with("WinDivvun")
+        {
+            return None;
+        }
+
+        self.install_location
+            .as_ref()
+            .map(|install_path| PathBuf::from(install_path.to_string()))
+    }
+
     pub fn validate_libreoffice(&self) -> Option<LibreOffice> {
         if !self
             .display_name
@@ -343,35 +814,46 @@ impl CandidateRegKey {
     }
 }
 
-pub(crate) fn get_candidate_regkeys() -> Vec<CandidateRegKey> {
-    let regkey = Hive::LocalMachine
-        .open(KEY_UNINSTALL, Security::Read | Security::Wow6464Key)
-        .unwrap();
-    let regkey_wow64 = Hive::LocalMachine
-        .open(KEY_UNINSTALL, Security::Read | Security::Wow6432Key)
-        .unwrap();
-
-    let iter = regkey
-        .keys()
-        .flat_map(Result::ok)
-        .chain(regkey_wow64.keys().flat_map(Result::ok));
+pub(crate) fn get_candidate_regkeys(registry: &dyn RegistryBackend) -> Vec<CandidateRegKey> {
+    let regkey = KeyPath::new(Hive::LocalMachine, View::Registry64, KEY_UNINSTALL);
+    let regkey_wow64 = KeyPath::new(Hive::LocalMachine, View::Registry32, KEY_UNINSTALL);
 
-    iter.filter_map(|keyref| {
-        let subkey = match keyref.open(Security::Read) {
+    let iter = vec![regkey, regkey_wow64].into_iter().flat_map(|parent| {
+        let names = match registry.subkeys(&parent) {
             Ok(v) => v,
             Err(e) => {
                 log::error!("{:?}", e);
-                return None;
+                vec![]
             }
         };
-        log::trace!("Parsing: {}", regkey);
-        Some(CandidateRegKey::from(&subkey))
+        names.into_iter().map(move |name| parent.join(&name))
+    });
+
+    iter.filter_map(|subkey| {
+        log::trace!("Parsing: {}", subkey);
+        match CandidateRegKey::open(registry, &subkey) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                log::error!("{:?}", e);
+                None
+            }
+        }
     })
     .collect::<Vec<_>>()
 }
 
-fn detect_ms_office() -> Vec<Office> {
-    let office_installs = get_candidate_regkeys()
+/// Where WinDivvun is installed, according to its Uninstall entry.
+pub(crate) fn detect_windivvun(registry: &dyn RegistryBackend) -> Option<PathBuf> {
+    let install_path = get_candidate_regkeys(registry)
+        .iter()
+        .find_map(|candidate| candidate.validate_windivvun())?;
+
+    log::info!("Found WinDivvun at {}", install_path.display());
+    Some(install_path)
+}
+
+pub(crate) fn detect_ms_office(registry: &dyn RegistryBackend) -> Vec<Office> {
+    let office_installs = get_candidate_regkeys(registry)
         .iter()
         .filter_map(|candidate| candidate.validate_office())
         .collect::<Vec<_>>();
@@ -394,63 +876,382 @@ enum InstallMethod {
     Msi,
 }
 
-fn refresh_libreoffice_spellchecker() {
-    let unopkg_path = libreoffice::find_unopkg();
+fn refresh_libreoffice_spellchecker(
+    registry: &dyn RegistryBackend,
+    fs: &dyn FileSystem,
+    plan: &mut Plan,
+    scope: Scope,
+    options: &RefreshOptions,
+) {
+    if libreoffice::is_current(fs, scope) {
+        log::info!("The LibreOffice spellchecker extension is up to date");
+        return;
+    }
+
+    let unopkg_path = libreoffice::find_unopkg(registry, fs);
     if unopkg_path.is_none() {
         log::error!("Couldn't find unopkg, aborting LibreOffice spellechecker installation");
         return;
     }
     let unopkg_path = unopkg_path.unwrap();
 
-    let install_path = libreoffice::get_speller_install_directory();
-    if let Err(e) = std::fs::create_dir_all(&install_path) {
-        log::error!(
-            "Failed to create install directory for LibreOffice speller: {}",
-            e
-        );
+    let install_path = libreoffice::get_speller_install_directory(scope);
+    plan.push(Step::CreateDir { path: install_path });
+
+    let oxt_path = libreoffice::oxt_path(scope);
+    plan.push(Step::WriteFile {
+        path: oxt_path.clone(),
+        contents: Contents::LibreofficeExtension,
+    });
+
+    // We don't care if removing fails, it means that it wasn't installed in the first place
+    plan.push(Step::Unopkg {
+        unopkg: unopkg_path.clone(),
+        args: libreoffice::unopkg_args("remove", &options.libreoffice_extension_id, scope),
+        allow_failure: true,
+    });
+
+    plan.push(Step::Unopkg {
+        unopkg: unopkg_path,
+        args: libreoffice::unopkg_args("add", &oxt_path.to_string_lossy(), scope),
+        allow_failure: false,
+    });
+}
+
+/// Uninstalls the extension if spelli installed it, removing the .oxt so the
+/// next refresh knows it is gone.
+fn remove_libreoffice_spellchecker(
+    registry: &dyn RegistryBackend,
+    fs: &dyn FileSystem,
+    plan: &mut Plan,
+    scope: Scope,
+    options: &RefreshOptions,
+) {
+    let oxt_path = libreoffice::oxt_path(scope);
+    if !fs.exists(&oxt_path) {
         return;
     }
 
-    let oxt_path = install_path.join("divvunspell.oxt");
-    if let Err(e) = std::fs::write(&oxt_path, OXT_DATA) {
-        log::error!("Failed to write divvunspell.oxt: {}", e);
-        return;
+    if let Some(unopkg_path) = libreoffice::find_unopkg(registry, fs) {
+        // We don't care if removing fails, it means that it wasn't installed in the first place
+        plan.push(Step::Unopkg {
+            unopkg: unopkg_path,
+            args: libreoffice::unopkg_args("remove", &options.libreoffice_extension_id, scope),
+            allow_failure: true,
+        });
     }
 
-    log::info!("Trying to remove previous installation if it exists");
-    let result = Command::new(&unopkg_path)
-        .args(&["remove", "--shared", "no.divvun.DivvunSpell"])
-        .output();
+    plan.push(Step::RemoveFile { path: oxt_path });
+}
 
-    match result {
-        Err(e) => {
-            log::error!("Failed to start unokpg process: {}", e);
-            return;
-        }
-        Ok(v) => {
-            // We don't care if it fails here, it means that it wasn't installed in the first place
-            log::debug!("Unopkg remove exited with status: {}", v.status)
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryRegistry;
+    use crate::filesystem::MemoryFileSystem;
+    use crate::testing::{self, FixedClock};
+
+    const SE: &str = r#"
+[spellers]
+se = "se.zhfst"
+"#;
+
+    const BASE_PATH: &str = r"SOFTWARE\Microsoft\Office\16.0\User Settings\WinDivvun";
+
+    #[test]
+    fn first_refresh_registers_with_office() {
+        let (registry, mut fs) = testing::machine();
+        let dir = testing::add_package(&mut fs, "se", SE, &["se.zhfst"]);
+        let options = RefreshOptions::default();
+        let clock = FixedClock::at(1000);
+
+        let plan = plan(
+            &registry,
+            &fs,
+            &clock,
+            &testing::roots(),
+            Scope::Machine,
+            &options,
+        )
+        .unwrap();
+        plan.apply(&registry).unwrap();
+
+        let speller = dir.join("se.zhfst").to_string_lossy().to_string();
+        let spellers = reg::spellers_key(Scope::Machine);
+        assert_eq!(
+            registry.value(&spellers, "se").unwrap(),
+            Some(Value::String(speller.clone()))
+        );
+
+        let record = reg::create_records_key(BASE_PATH, &options).join("se");
+        assert_eq!(
+            registry.value(&record, "LEX").unwrap(),
+            Some(Value::String(speller))
+        );
+        assert_eq!(
+            registry.value(&record, "DLL64").unwrap(),
+            Some(Value::String(options.mso_dlls.dll64.clone()))
+        );
+
+        let base = KeyPath::new(Hive::LocalMachine, View::Registry64, BASE_PATH);
+        assert_eq!(
+            registry.value(&base, "Count").unwrap(),
+            Some(Value::U32(1000))
+        );
     }
 
-    let result = Command::new(unopkg_path)
-        .args(&["add", "--shared", oxt_path.to_str().unwrap()])
-        .output();
+    #[test]
+    fn repeat_refresh_plans_nothing() {
+        let (registry, mut fs) = testing::machine();
+        testing::add_package(&mut fs, "se", SE, &["se.zhfst"]);
+        let options = RefreshOptions::default();
+        let clock = FixedClock::at(1000);
+        let roots = testing::roots();
 
-    match result {
-        Err(e) => {
-            log::error!("Failed to start unokpg process: {}", e);
-            return;
-        }
-        Ok(v) => {
-            // We don't care if it fails here, it means that it wasn't installed in the first place
-            log::debug!("Unopkg install exited with status: {}", v.status);
-            if !v.status.success() {
-                log::error!("Failed to install the libreoffice extension");
-                log::error!("stdout: {}", &String::from_utf8_lossy(&v.stdout));
-                log::error!("stderr: {}", &String::from_utf8_lossy(&v.stderr));
-                return;
-            }
+        plan(&registry, &fs, &clock, &roots, Scope::Machine, &options)
+            .unwrap()
+            .apply(&registry)
+            .unwrap();
+
+        clock.set(2000);
+        let plan = plan(&registry, &fs, &clock, &roots, Scope::Machine, &options).unwrap();
+        assert_eq!(plan.steps, vec![]);
+    }
+
+    #[test]
+    fn removed_packages_are_deregistered() {
+        let (registry, mut fs) = testing::machine();
+        testing::add_package(&mut fs, "se", SE, &["se.zhfst"]);
+        let options = RefreshOptions::default();
+        let clock = FixedClock::at(1000);
+        let roots = testing::roots();
+
+        plan(&registry, &fs, &clock, &roots, Scope::Machine, &options)
+            .unwrap()
+            .apply(&registry)
+            .unwrap();
+
+        let (_, fs) = testing::machine();
+        clock.set(2000);
+        plan(&registry, &fs, &clock, &roots, Scope::Machine, &options)
+            .unwrap()
+            .apply(&registry)
+            .unwrap();
+
+        let spellers = reg::spellers_key(Scope::Machine);
+        assert_eq!(registry.value(&spellers, "se").unwrap(), Some(Value::None));
+        let record = reg::delete_records_key(BASE_PATH, &options).join("se");
+        assert!(registry.key_exists(&record).unwrap());
+        let base = KeyPath::new(Hive::LocalMachine, View::Registry64, BASE_PATH);
+        assert_eq!(
+            registry.value(&base, "Count").unwrap(),
+            Some(Value::U32(2000))
+        );
+    }
+
+    fn has_libreoffice_steps(plan: &Plan) -> bool {
+        plan.steps.iter().any(|x| {
+            matches!(
+                x,
+                Step::WriteFile { .. } | Step::RemoveFile { .. } | Step::Unopkg { .. }
+            )
+        })
+    }
+
+    #[test]
+    fn a_current_libreoffice_extension_is_left_alone() {
+        let (registry, mut fs) = testing::machine();
+        testing::add_libreoffice(&registry, &mut fs);
+        testing::add_package(&mut fs, "se", SE, &["se.zhfst"]);
+        let options = RefreshOptions::default();
+        let clock = FixedClock::at(1000);
+        let roots = testing::roots();
+
+        let first = plan(&registry, &fs, &clock, &roots, Scope::Machine, &options).unwrap();
+        assert!(has_libreoffice_steps(&first));
+        testing::apply_registry_steps(&first, &registry);
+        fs.add_speller_file(
+            &libreoffice::oxt_path(Scope::Machine),
+            None,
+            Some(checksum::sha256(libreoffice::OXT_DATA)),
+            None,
+        );
+
+        let plan = plan(&registry, &fs, &clock, &roots, Scope::Machine, &options).unwrap();
+        assert_eq!(plan.steps, vec![]);
+    }
+
+    #[test]
+    fn spellers_not_for_libreoffice_are_kept_out_of_the_spellers_key() {
+        let (registry, mut fs) = testing::machine();
+        let text = "version = 2\n[spellers]\nse = { file = \"se.zhfst\", hosts = [\"office\"] }\n";
+        let dir = testing::add_package(&mut fs, "se", text, &["se.zhfst"]);
+        let options = RefreshOptions::default();
+        let clock = FixedClock::at(1000);
+        let roots = testing::roots();
+        let speller = Some(Value::String(
+            dir.join("se.zhfst").to_string_lossy().to_string(),
+        ));
+        let spellers = reg::spellers_key(Scope::Machine);
+        let hidden = reg::hidden_spellers_key(Scope::Machine);
+        let record = reg::create_records_key(BASE_PATH, &options).join("se");
+
+        let first = plan(&registry, &fs, &clock, &roots, Scope::Machine, &options).unwrap();
+        testing::apply_registry_steps(&first, &registry);
+        assert_eq!(registry.value(&spellers, "se").unwrap(), None);
+        assert_eq!(registry.value(&hidden, "se").unwrap(), speller);
+        assert_eq!(registry.value(&record, "LEX").unwrap(), speller);
+
+        let repeat = plan(&registry, &fs, &clock, &roots, Scope::Machine, &options).unwrap();
+        assert_eq!(repeat.steps, vec![]);
+
+        let text = "version = 2\n[spellers]\nse = { file = \"se.zhfst\", hosts = [\"libreoffice\", \"windows\"] }\n";
+        testing::add_package(&mut fs, "se", text, &["se.zhfst"]);
+        clock.set(2000);
+        let second = plan(&registry, &fs, &clock, &roots, Scope::Machine, &options).unwrap();
+        testing::apply_registry_steps(&second, &registry);
+        assert_eq!(registry.value(&spellers, "se").unwrap(), speller);
+        assert_eq!(registry.value(&hidden, "se").unwrap(), None);
+        assert!(!registry.key_exists(&record).unwrap());
+    }
+
+    #[test]
+    fn spellers_not_for_office_do_not_need_the_mso_dlls() {
+        let mut fs = MemoryFileSystem::new();
+        fs.add_dir(Path::new(testing::SPELLER_ROOT));
+        let text =
+            "version = 2\n[spellers]\nse = { file = \"se.zhfst\", hosts = [\"libreoffice\", \"windows\"] }\n";
+        testing::add_package(&mut fs, "se", text, &["se.zhfst"]);
+        let registry = MemoryRegistry::new();
+        let options = RefreshOptions::default();
+        let clock = FixedClock::at(1000);
+        let roots = testing::roots();
+
+        plan(&registry, &fs, &clock, &roots, Scope::Machine, &options).unwrap();
+
+        let text = "version = 2\n[spellers]\nse = { file = \"se.zhfst\", hosts = [\"office\"] }\n";
+        testing::add_package(&mut fs, "se", text, &["se.zhfst"]);
+        assert!(plan(&registry, &fs, &clock, &roots, Scope::Machine, &options).is_err());
+    }
+
+    #[test]
+    fn the_libreoffice_extension_is_only_removed_if_installed() {
+        let (registry, mut fs) = testing::machine();
+        testing::add_libreoffice(&registry, &mut fs);
+        let text = "version = 2\n[spellers]\nse = { file = \"se.zhfst\", hosts = [\"office\"] }\n";
+        testing::add_package(&mut fs, "se", text, &["se.zhfst"]);
+        let options = RefreshOptions::default();
+        let clock = FixedClock::at(1000);
+        let roots = testing::roots();
+
+        let first = plan(&registry, &fs, &clock, &roots, Scope::Machine, &options).unwrap();
+        assert!(!has_libreoffice_steps(&first));
+        testing::apply_registry_steps(&first, &registry);
+
+        let oxt_path = libreoffice::oxt_path(Scope::Machine);
+        fs.add_speller_file(&oxt_path, None, None, None);
+        let plan = plan(&registry, &fs, &clock, &roots, Scope::Machine, &options).unwrap();
+        assert!(matches!(plan.steps.first(), Some(Step::Unopkg { .. })));
+        assert_eq!(
+            plan.steps.last(),
+            Some(&Step::RemoveFile { path: oxt_path })
+        );
+    }
+
+    #[test]
+    fn earlier_speller_roots_win() {
+        let (registry, mut fs) = testing::machine();
+        testing::add_package(&mut fs, "se", SE, &["se.zhfst"]);
+        let site = Path::new(r"C:\ProgramData\WinDivvun\Spellers");
+        let dir = site.join("se");
+        fs.add_dir(site);
+        fs.add_dir(&dir);
+        fs.add_file(&dir.join("speller.toml"), Some(SE.to_string()));
+        testing::add_zhfst(&mut fs, &dir.join("se.zhfst"));
+        let roots = vec![site.to_path_buf(), PathBuf::from(testing::SPELLER_ROOT)];
+
+        plan(
+            &registry,
+            &fs,
+            &FixedClock::at(1000),
+            &roots,
+            Scope::Machine,
+            &RefreshOptions::default(),
+        )
+        .unwrap()
+        .apply(&registry)
+        .unwrap();
+
+        let speller = dir.join("se.zhfst").to_string_lossy().to_string();
+        assert_eq!(
+            registry
+                .value(&reg::spellers_key(Scope::Machine), "se")
+                .unwrap(),
+            Some(Value::String(speller))
+        );
+    }
+
+    #[test]
+    fn corrupt_zhfsts_are_skipped() {
+        let (_, mut fs) = testing::machine();
+        let text = "[spellers]\nse = \"se.zhfst\"\nsma = \"sma.zhfst\"\n";
+        let dir = testing::add_package(&mut fs, "se", text, &["se.zhfst"]);
+        // Not a zip archive at all
+        fs.add_speller_file(&dir.join("sma.zhfst"), None, None, None);
+
+        let tags = registrations(&fs, &testing::roots(), &[Format::Zhfst])
+            .unwrap()
+            .into_iter()
+            .flat_map(|x| x.tags)
+            .collect::<Vec<_>>();
+        assert!(tags.contains(&"se".to_string()));
+        assert!(!tags.iter().any(|x| x.starts_with("sma")));
+    }
+
+    #[test]
+    fn spellers_with_the_wrong_sha256_are_skipped() {
+        let (_, mut fs) = testing::machine();
+        let actual = "0".repeat(64);
+        let text = format!(
+            "version = 2\n[spellers]\nse = {{ file = \"se.zhfst\", sha256 = \"{}\" }}\nsma = {{ file = \"sma.zhfst\", sha256 = \"{}\" }}\n",
+            actual,
+            "1".repeat(64)
+        );
+        let dir = testing::add_package(&mut fs, "se", &text, &[]);
+        let entries = [
+            "index.xml",
+            "acceptor.default.hfst",
+            "errmodel.default.hfst",
+        ];
+        for file in ["se.zhfst", "sma.zhfst"].iter() {
+            fs.add_speller_file(
+                &dir.join(file),
+                Some(entries.iter().map(|x| x.to_string()).collect()),
+                Some(actual.clone()),
+                None,
+            );
         }
+
+        let tags = registrations(&fs, &testing::roots(), &[Format::Zhfst])
+            .unwrap()
+            .into_iter()
+            .flat_map(|x| x.tags)
+            .collect::<Vec<_>>();
+        assert!(tags.contains(&"se".to_string()));
+        assert!(!tags.iter().any(|x| x.starts_with("sma")));
+    }
+
+    #[test]
+    fn invalid_manual_tags_are_skipped() {
+        let (_, mut fs) = testing::machine();
+        testing::add_package(&mut fs, "se", SE, &["se.zhfst"]);
+        let manual = std::iter::once(("not a tag!".to_string(), None)).collect();
+
+        let tags = desired_registrations(&fs, &testing::roots(), &manual, &[Format::Zhfst])
+            .unwrap()
+            .into_iter()
+            .flat_map(|x| x.tags)
+            .collect::<Vec<_>>();
+        assert!(tags.contains(&"se".to_string()));
     }
 }
@@ -1,198 +1,1001 @@
 use std::convert::TryInto;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use std::{collections::BTreeMap, path::Path};
-
-use registry::{key, value, Data, Hive, RegKey, Security};
-use widestring::U16CString;
+use std::fmt::Display;
+use std::time::Duration;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use crate::backend::{Hive, KeyPath, RegistryBackend, Value, View};
+use crate::clock::{self, Clock};
+use crate::format::Format;
+use crate::host::{self, Host};
+use crate::mso;
+use crate::plan::Plan;
+use crate::refresh::RefreshOptions;
 
 #[derive(Debug, thiserror::Error)]
 pub(crate) enum Error {
+    #[error("Key not found: {0}")]
+    NotFound(String),
+
+    #[cfg(windows)]
     #[error("Key error")]
-    Key(#[from] key::Error),
+    Key(#[from] registry::key::Error),
 
+    #[cfg(windows)]
     #[error("Value error")]
-    Value(#[from] value::Error),
+    Value(#[from] registry::value::Error),
 }
 
-pub(crate) fn open_key() -> Result<RegKey, Error> {
-    Ok(Hive::LocalMachine.create(
-        r"SOFTWARE\WinDivvun\Spellers".to_string(),
-        Security::AllAccess,
-    )?)
+const WINDIVVUN_KEY: &str = r"SOFTWARE\WinDivvun";
+const SPELLERS_KEY: &str = r"SOFTWARE\WinDivvun\Spellers";
+const MANUAL_SPELLERS_KEY: &str = r"SOFTWARE\WinDivvun\ManualSpellers";
+
+/// Whether spellers are installed for every user of the machine, which needs
+/// administrator rights, or only for the user running spelli.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Scope {
+    Machine,
+    User,
 }
 
-pub(crate) fn nuke_key() -> Result<(), Error> {
-    let langs = Langs::new()?;
-    deregister_langs(
-        &langs
-            .create
-            .keys()
-            .map(|x| x.to_string_lossy())
-            .collect::<Vec<_>>(),
-    )?;
-    // crate::refresh::refresh();
-    log::info!("All languages have now been marked as deregistered.");
-    Ok(())
+impl Scope {
+    pub(crate) fn hive(self) -> Hive {
+        match self {
+            Scope::Machine => Hive::LocalMachine,
+            Scope::User => Hive::CurrentUser,
+        }
+    }
+
+    /// Where speller packages for this scope are installed.
+    pub(crate) fn speller_dir(self) -> PathBuf {
+        match self {
+            Scope::Machine => PathBuf::from(SPELLER_DIR),
+            Scope::User => user_local_dir().join("WinDivvun").join("Spellers"),
+        }
+    }
 }
 
-const BASE_PROOF_TOOL_PATH: &str = r"SOFTWARE\Microsoft\Shared Tools\Proofing Tools\1.0\Override";
-const PATH_CREATE: &str = "Create";
-const PATH_DELETE: &str = "Delete";
+/// The current user's local, non-roaming application data directory.
+#[cfg(windows)]
+pub(crate) fn user_local_dir() -> PathBuf {
+    pathos::user::local_dir().unwrap().to_path_buf()
+}
 
-pub(crate) const SPELLER_DIR: &str = r"C:\Program Files\WinDivvun\Spellers\";
-const DIVVUNSPELL_MSO_32: &str = r"C:\Program Files\WinDivvun\i686\divvunspellmso.dll";
-const DIVVUNSPELL_MSO_64: &str = r"C:\Program Files\WinDivvun\x86_64\divvunspellmso.dll";
+#[cfg(not(windows))]
+pub(crate) fn user_local_dir() -> PathBuf {
+    pathos::user::data_dir().unwrap().to_path_buf()
+}
 
-fn add_create_key(base_path: &str, lang_id: &str, speller_path: &str) -> Result<(), Error> {
-    // Check if value exists in Delete
-    let full_delete_path = vec![base_path, PATH_DELETE, BASE_PROOF_TOOL_PATH, lang_id].join(r"\");
+impl Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            Scope::Machine => "machine",
+            Scope::User => "user",
+        })
+    }
+}
 
-    match Hive::LocalMachine.open(full_delete_path, Security::AllAccess | Security::Wow6464Key) {
-        Err(key::Error::NotFound(_, _)) => { /* ignore, it's already been deleted */ }
-        Err(e) => return Err(e)?,
-        Ok(regkey) => {
-            regkey.delete_self(true)?
-        }
-    };
+/// Value on the WinDivvun key recording when the Spellers key last changed, in
+/// the same units as Count, so stale Office User Settings paths can be found.
+pub(crate) const SPELLERS_UPDATED: &str = "SpellersUpdated";
 
-    // Now to create the Create record
-    let full_create_path = vec![base_path, PATH_CREATE, BASE_PROOF_TOOL_PATH, lang_id].join(r"\");
-
-    let regkey =
-        Hive::LocalMachine.create(full_create_path, Security::AllAccess | Security::Wow6464Key)?;
-    regkey.set_value("LEX", &Data::String(speller_path.try_into().unwrap()))?;
-    regkey.set_value("LEX64", &Data::String(speller_path.try_into().unwrap()))?;
-    regkey.set_value("DLL", &Data::String(DIVVUNSPELL_MSO_32.try_into().unwrap()))?;
-    regkey.set_value(
-        "DLL64",
-        &Data::String(DIVVUNSPELL_MSO_64.try_into().unwrap()),
-    )?;
+pub(crate) fn windivvun_key(scope: Scope) -> KeyPath {
+    KeyPath::new(scope.hive(), View::Default, WINDIVVUN_KEY)
+}
 
-    Ok(())
+pub(crate) fn spellers_key(scope: Scope) -> KeyPath {
+    KeyPath::new(scope.hive(), View::Default, SPELLERS_KEY)
+}
+
+/// Spellers registered with `register`, by the tag they were registered for.
+/// Refresh reads these alongside the speller packages instead of replacing them.
+pub(crate) fn manual_spellers_key(scope: Scope) -> KeyPath {
+    KeyPath::new(scope.hive(), View::Default, MANUAL_SPELLERS_KEY)
 }
 
-fn add_delete_key(base_path: &str, lang_id: &str) -> Result<(), Error> {
-    // Check if value exists in Create
-    let full_create_path = vec![base_path, PATH_CREATE, BASE_PROOF_TOOL_PATH, lang_id].join(r"\");
+/// The origin of each tag in the Spellers key, one subkey per tag.
+pub(crate) fn origins_key(scope: Scope) -> KeyPath {
+    spellers_key(scope).join("Origins")
+}
+
+/// Registered tags that are not for the hosts reading the Spellers key, with
+/// their speller paths. Those hosts only read the values of the Spellers key,
+/// so tags kept here are only used through Office's records.
+pub(crate) fn hidden_spellers_key(scope: Scope) -> KeyPath {
+    spellers_key(scope).join("Hidden")
+}
+
+/// When each tombstone in the Spellers key was written, in the same units as Count.
+pub(crate) fn tombstones_key(scope: Scope) -> KeyPath {
+    spellers_key(scope).join("Tombstones")
+}
+
+/// What provided a registered tag.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Source {
+    /// A speller package directory containing a speller.toml, in one of the speller roots.
+    Package { root: PathBuf, dir: PathBuf },
+    /// A registration made with `register`.
+    Manual,
+}
 
-    match Hive::LocalMachine.open(full_create_path, Security::AllAccess | Security::Wow6464Key) {
-        Err(key::Error::NotFound(_, _)) => { /* ignore, it's already been deleted */ }
-        Err(e) => return Err(e)?,
-        Ok(regkey) => {
-            regkey.delete_self(true)?
+/// Where a registered tag came from, so conflicts between packages can be traced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Origin {
+    pub(crate) source: Source,
+    /// The tag as declared in speller.toml or given to `register`.
+    pub(crate) entry: String,
+    /// Whether the tag was derived from `entry` rather than being `entry` itself.
+    pub(crate) derived: bool,
+    /// Whether the tag is one of the aliases declared for `entry`.
+    pub(crate) alias: bool,
+    /// The format of the registered speller file. Unknown for older records.
+    pub(crate) format: Option<Format>,
+    /// The hosts the tag is registered with. Older records have all of them.
+    pub(crate) hosts: Vec<Host>,
+}
+
+impl Origin {
+    pub(crate) fn new(source: Source, entry: &str, tag: &str, format: Format) -> Origin {
+        Origin {
+            source,
+            entry: entry.to_string(),
+            derived: !tag.eq_ignore_ascii_case(entry),
+            alias: false,
+            format: Some(format),
+            hosts: Host::ALL.to_vec(),
+        }
+    }
+
+    /// The origin of `tag` when it is a declared alias of `entry`.
+    pub(crate) fn alias(source: Source, entry: &str, tag: &str, format: Format) -> Origin {
+        Origin {
+            derived: false,
+            alias: true,
+            ..Origin::new(source, entry, tag, format)
         }
+    }
+
+    fn read(registry: &dyn RegistryBackend, key: &KeyPath) -> Result<Option<Origin>, Error> {
+        let string = |name| -> Result<Option<String>, Error> {
+            match registry.value(key, name)? {
+                Some(Value::String(s)) => Ok(Some(s)),
+                _ => Ok(None),
+            }
+        };
+
+        let source = match string("Source")?.as_deref() {
+            Some("package") => match string("Package")? {
+                Some(dir) => {
+                    let dir = PathBuf::from(dir);
+                    let root = match string("Root")? {
+                        Some(root) => PathBuf::from(root),
+                        None => dir.parent().map(Path::to_path_buf).unwrap_or_default(),
+                    };
+                    Source::Package { root, dir }
+                }
+                None => return Ok(None),
+            },
+            Some("manual") => Source::Manual,
+            _ => return Ok(None),
+        };
+        let entry = match string("Entry")? {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let derived = matches!(registry.value(key, "Derived")?, Some(Value::U32(x)) if x != 0);
+        let alias = matches!(registry.value(key, "Alias")?, Some(Value::U32(x)) if x != 0);
+        let format = string("Format")?.and_then(|x| x.parse().ok());
+        let hosts = match string("Hosts")? {
+            Some(hosts) => host::split(&hosts),
+            None => Host::ALL.to_vec(),
+        };
+
+        Ok(Some(Origin {
+            source,
+            entry,
+            derived,
+            alias,
+            format,
+            hosts,
+        }))
+    }
+
+    fn write(&self, plan: &mut Plan, key: &KeyPath) {
+        plan.create_key(key.clone());
+        match &self.source {
+            Source::Package { root, dir } => {
+                plan.set_value(key, "Source", Value::String("package".to_string()));
+                plan.set_value(
+                    key,
+                    "Root",
+                    Value::String(root.to_string_lossy().to_string()),
+                );
+                plan.set_value(
+                    key,
+                    "Package",
+                    Value::String(dir.to_string_lossy().to_string()),
+                );
+            }
+            Source::Manual => {
+                plan.set_value(key, "Source", Value::String("manual".to_string()));
+                plan.delete_value(key, "Root");
+                plan.delete_value(key, "Package");
+            }
+        }
+        plan.set_value(key, "Entry", Value::String(self.entry.clone()));
+        plan.set_value(key, "Derived", Value::U32(self.derived as u32));
+        plan.set_value(key, "Alias", Value::U32(self.alias as u32));
+        match self.format {
+            Some(format) => plan.set_value(key, "Format", Value::String(format.to_string())),
+            None => plan.delete_value(key, "Format"),
+        }
+        if self.is_for_all_hosts() {
+            plan.delete_value(key, "Hosts");
+        } else {
+            plan.set_value(key, "Hosts", Value::String(host::join(&self.hosts)));
+        }
+    }
+
+    fn is_for_all_hosts(&self) -> bool {
+        Host::ALL.iter().all(|x| self.hosts.contains(x))
+    }
+
+    /// Whether the tag belongs in the Spellers key rather than the Hidden key.
+    pub(crate) fn is_in_spellers_key(&self) -> bool {
+        Host::SPELLERS_KEY_READERS
+            .iter()
+            .any(|x| self.hosts.contains(x))
+    }
+}
+
+impl Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.source {
+            Source::Package { root, dir } => write!(
+                f,
+                "root {}, package {}, entry '{}'",
+                root.display(),
+                dir.strip_prefix(root).unwrap_or(dir).display(),
+                self.entry
+            )?,
+            Source::Manual => write!(f, "manual registration '{}'", self.entry)?,
+        }
+        if self.alias {
+            write!(f, " (alias)")?;
+        } else if self.derived {
+            write!(f, " (derived)")?;
+        } else {
+            write!(f, " (declared)")?;
+        }
+        if let Some(format) = self.format {
+            write!(f, ", {}", format)?;
+        }
+        if !self.is_for_all_hosts() {
+            write!(f, ", only for [{}]", host::join(&self.hosts))?;
+        }
+        Ok(())
+    }
+}
+
+/// The recorded origin of every tag in the Spellers key that has one.
+pub(crate) fn origins(
+    registry: &dyn RegistryBackend,
+    scope: Scope,
+) -> Result<BTreeMap<String, Origin>, Error> {
+    let key = origins_key(scope);
+    let tags = match registry.subkeys(&key) {
+        Err(Error::NotFound(_)) => vec![],
+        result => result?,
     };
 
-    // Now to create the Create record
-    let full_delete_path = vec![base_path, PATH_DELETE, BASE_PROOF_TOOL_PATH, lang_id].join(r"\");
+    let mut out = BTreeMap::new();
+    for tag in tags {
+        match Origin::read(registry, &key.join(&tag))? {
+            Some(origin) => {
+                out.insert(tag, origin);
+            }
+            None => log::warn!("Unreadable origin for {}", &tag),
+        }
+    }
 
-    let regkey =
-        Hive::LocalMachine.create(full_delete_path, Security::AllAccess | Security::Wow6464Key)?;
-    regkey.set_value("LEX", &Data::String("".try_into().unwrap()))?;
-    regkey.set_value("LEX64", &Data::String("".try_into().unwrap()))?;
-    regkey.set_value("DLL", &Data::String("".try_into().unwrap()))?;
-    regkey.set_value("DLL64", &Data::String("".try_into().unwrap()))?;
+    Ok(out)
+}
 
-    Ok(())
+/// Manual registrations by tag. `None` means the tag was deregistered with
+/// `deregister` and stays deregistered even though a package provides it.
+pub(crate) fn manual_registrations(
+    registry: &dyn RegistryBackend,
+    scope: Scope,
+) -> Result<BTreeMap<String, Option<String>>, Error> {
+    let values = match registry.values(&manual_spellers_key(scope)) {
+        Err(Error::NotFound(_)) => vec![],
+        result => result?,
+    };
+
+    let mut out = BTreeMap::new();
+    for (name, data) in values {
+        match data {
+            Value::String(path) => {
+                out.insert(name, Some(path));
+            }
+            Value::None => {
+                out.insert(name, None);
+            }
+            unhandled => log::warn!("Unhandled data for {}: {:?}", &name, unhandled),
+        }
+    }
+
+    Ok(out)
 }
 
-pub(crate) fn register_langs(names: &[String], path: &Path) -> Result<(), Error> {
-    let key = open_key()?;
-    let display = path.to_string_lossy().to_string();
-    let value: U16CString = display.clone().try_into().unwrap();
+/// Records that the Spellers key is changing, returning the new marker. Like
+/// Count, the marker always moves forward, even if the clock went backwards.
+pub(crate) fn mark_spellers_updated(
+    registry: &dyn RegistryBackend,
+    plan: &mut Plan,
+    scope: Scope,
+    clock: &dyn Clock,
+) -> Result<u32, Error> {
+    let next = next_counter(spellers_updated(registry, scope)?, clock);
+    plan.set_counter(&windivvun_key(scope), SPELLERS_UPDATED, next);
+    Ok(next)
+}
 
-    for name in names {
-        log::info!("Setting '{}' -> '{}'", name, &display);
-        key.set_value(name, &Data::String(value.clone()))?;
+pub(crate) fn spellers_updated(
+    registry: &dyn RegistryBackend,
+    scope: Scope,
+) -> Result<Option<u32>, Error> {
+    match registry.value(&windivvun_key(scope), SPELLERS_UPDATED)? {
+        Some(Value::U32(x)) => Ok(Some(x)),
+        _ => Ok(None),
     }
+}
 
-    log::info!(
-        "Successfully set {} language tags for '{}'.",
-        names.len(),
-        path.display()
+/// Marks every registered language as deregistered, returning the resulting state.
+pub(crate) fn nuke_key(
+    registry: &dyn RegistryBackend,
+    plan: &mut Plan,
+    scope: Scope,
+    clock: &dyn Clock,
+) -> Result<Langs, Error> {
+    let mut langs = Langs::new(registry, scope)?;
+    langs.deregister(
+        plan,
+        &langs.create.keys().cloned().collect::<Vec<_>>(),
+        clock,
     );
+    mark_spellers_updated(registry, plan, scope, clock)?;
+    log::info!("All languages will be marked as deregistered.");
+    Ok(langs)
+}
 
-    Ok(())
+pub(crate) const BASE_PROOF_TOOL_PATH: &str =
+    r"SOFTWARE\Microsoft\Shared Tools\Proofing Tools\1.0\Override";
+const PATH_CREATE: &str = "Create";
+const PATH_DELETE: &str = "Delete";
+/// The key spelli owns under each of Office's User Settings keys.
+pub(crate) const USER_SETTINGS_NAME: &str = "WinDivvun";
+
+pub(crate) const SPELLER_DIR: &str = r"C:\Program Files\WinDivvun\Spellers\";
+pub(crate) const DIVVUNSPELL_MSO_32: &str = r"C:\Program Files\WinDivvun\i686\divvunspellmso.dll";
+pub(crate) const DIVVUNSPELL_MSO_64: &str = r"C:\Program Files\WinDivvun\x86_64\divvunspellmso.dll";
+
+/// The key holding one Create record per language tag under a User Settings path.
+pub(crate) fn create_records_key(base_path: &str, options: &RefreshOptions) -> KeyPath {
+    KeyPath::new(
+        Hive::LocalMachine,
+        View::Registry64,
+        [base_path, PATH_CREATE, options.proof_tool_path.as_str()].join(r"\"),
+    )
 }
 
-pub(crate) fn set_regkey_counter(base_path: &str) -> Result<(), Error> {
-    let regkey = Hive::LocalMachine.create(base_path, Security::Write | Security::Wow6464Key)?;
-    // Our epoch starts at 2020-01-01.
-    let our_epoch = UNIX_EPOCH + Duration::from_secs(1577836800);
-    let ts: u32 = SystemTime::now()
-        .duration_since(our_epoch)
-        .unwrap()
-        .as_secs()
-        .try_into()
-        .unwrap();
-    regkey.set_value("Count", &Data::U32(ts))?;
-    // No idea why this is needed, but nearly all other keys have it, so we do too.
-    regkey.set_value("Order", &Data::U32(1))?;
-    Ok(())
+/// The key holding one Delete record per language tag under a User Settings path.
+pub(crate) fn delete_records_key(base_path: &str, options: &RefreshOptions) -> KeyPath {
+    KeyPath::new(
+        Hive::LocalMachine,
+        View::Registry64,
+        [base_path, PATH_DELETE, options.proof_tool_path.as_str()].join(r"\"),
+    )
+}
+
+/// Office reads per-user overrides directly, without Create/Delete records or Count.
+pub(crate) fn user_overrides_key(options: &RefreshOptions) -> KeyPath {
+    KeyPath::new(Hive::CurrentUser, View::Default, &options.proof_tool_path)
 }
 
-pub(crate) fn deregister_langs(names: &[String]) -> Result<(), Error> {
-    let key = open_key()?;
+fn proof_tool_key(base_path: &str, kind: &str, lang_id: &str, options: &RefreshOptions) -> KeyPath {
+    KeyPath::new(
+        Hive::LocalMachine,
+        View::Registry64,
+        [base_path, kind, options.proof_tool_path.as_str(), lang_id].join(r"\"),
+    )
+}
 
-    for name in names {
-        log::info!("Setting '{}' -> <None>", name);
-        key.set_value(name, &Data::None)?;
+/// Whether a record already exists with exactly these string values.
+fn record_matches(
+    registry: &dyn RegistryBackend,
+    key: &KeyPath,
+    values: &[(&str, &str)],
+) -> Result<bool, Error> {
+    for (name, expected) in values.iter() {
+        match registry.value(key, name)? {
+            Some(Value::String(s)) if s == *expected => {}
+            _ => return Ok(false),
+        }
     }
 
-    log::info!("Successfully unset {} language tags.", names.len());
+    Ok(true)
+}
+
+fn add_create_key(
+    plan: &mut Plan,
+    base_path: &str,
+    lang_id: &str,
+    speller_path: &str,
+    options: &RefreshOptions,
+) {
+    let dlls = &options.mso_dlls;
+    // Remove the Delete record if it exists
+    plan.delete_key(proof_tool_key(base_path, PATH_DELETE, lang_id, options));
+
+    // Now to create the Create record
+    let key = proof_tool_key(base_path, PATH_CREATE, lang_id, options);
+    plan.create_key(key.clone());
+    plan.set_value(&key, "LEX", Value::String(speller_path.to_string()));
+    plan.set_value(&key, "LEX64", Value::String(speller_path.to_string()));
+    plan.set_value(&key, "DLL", Value::String(dlls.dll32.clone()));
+    plan.set_value(&key, "DLL64", Value::String(dlls.dll64.clone()));
+}
+
+pub(crate) fn add_delete_key(
+    plan: &mut Plan,
+    base_path: &str,
+    lang_id: &str,
+    options: &RefreshOptions,
+) {
+    // Remove the Create record if it exists
+    plan.delete_key(proof_tool_key(base_path, PATH_CREATE, lang_id, options));
+
+    // Now to create the Delete record
+    let key = proof_tool_key(base_path, PATH_DELETE, lang_id, options);
+    plan.create_key(key.clone());
+    plan.set_value(&key, "LEX", Value::String("".to_string()));
+    plan.set_value(&key, "LEX64", Value::String("".to_string()));
+    plan.set_value(&key, "DLL", Value::String("".to_string()));
+    plan.set_value(&key, "DLL64", Value::String("".to_string()));
+}
 
+/// The current time in Count units, or one past `previous` if the clock is behind it.
+fn next_counter(previous: Option<u32>, clock: &dyn Clock) -> u32 {
+    let now = clock::counter(clock);
+    match previous {
+        Some(previous) if now <= previous => {
+            log::warn!("The clock is behind the stored counter {}", previous);
+            previous.saturating_add(1)
+        }
+        _ => now,
+    }
+}
+
+/// Bumps Count on a User Settings path. Office only processes the records when
+/// Count increases, so it is always moved past the stored value, and never left
+/// older than `spellers_updated`.
+pub(crate) fn set_regkey_counter(
+    registry: &dyn RegistryBackend,
+    plan: &mut Plan,
+    base_path: &str,
+    spellers_updated: Option<u32>,
+    clock: &dyn Clock,
+) -> Result<(), Error> {
+    let key = KeyPath::new(Hive::LocalMachine, View::Registry64, base_path);
+    let stored = match registry.value(&key, "Count")? {
+        Some(Value::U32(x)) => Some(x),
+        _ => None,
+    };
+    let count = next_counter(stored, clock).max(spellers_updated.unwrap_or(0));
+    plan.set_counter(&key, "Count", count);
+    // No idea why this is needed, but nearly all other keys have it, so we do too.
+    plan.set_value(&key, "Order", Value::U32(1));
     Ok(())
 }
 
+/// The language tags in the Spellers key, as they will be once the steps
+/// added to a plan by `register` and `deregister` have been applied.
 pub(crate) struct Langs {
-    pub(crate) create: BTreeMap<U16CString, U16CString>,
-    pub(crate) delete: Vec<U16CString>,
+    pub(crate) scope: Scope,
+    pub(crate) create: BTreeMap<String, String>,
+    /// The tags in `create` that are in the Hidden key rather than the Spellers key.
+    pub(crate) hidden: BTreeSet<String>,
+    pub(crate) delete: Vec<String>,
+    pub(crate) origins: BTreeMap<String, Origin>,
+    pub(crate) tombstones: BTreeMap<String, u32>,
 }
 
 impl Langs {
-    pub fn new() -> Result<Langs, Error> {
-        let key = open_key()?;
+    pub fn new(registry: &dyn RegistryBackend, scope: Scope) -> Result<Langs, Error> {
+        let key = spellers_key(scope);
+
+        let values = match registry.values(&key) {
+            Err(Error::NotFound(_)) => vec![],
+            result => result?,
+        };
+
+        let hidden = match registry.values(&hidden_spellers_key(scope)) {
+            Err(Error::NotFound(_)) => vec![],
+            result => result?,
+        };
+
+        let mut langs = Langs::from_values(scope, values, hidden);
+        langs.origins = origins(registry, scope)?;
+
+        let dates = match registry.values(&tombstones_key(scope)) {
+            Err(Error::NotFound(_)) => vec![],
+            result => result?,
+        };
+        for (name, data) in dates {
+            match data {
+                Value::U32(x) => {
+                    langs.tombstones.insert(name, x);
+                }
+                unhandled => log::warn!("Unhandled data for {}: {:?}", &name, unhandled),
+            }
+        }
+
+        Ok(langs)
+    }
 
+    /// Builds the state from the values of a Spellers key and its Hidden key.
+    pub fn from_values(
+        scope: Scope,
+        values: Vec<(String, Value)>,
+        hidden_values: Vec<(String, Value)>,
+    ) -> Langs {
         let mut create = BTreeMap::new();
+        let mut hidden = BTreeSet::new();
         let mut delete = vec![];
 
-        for (name, data) in key.values().flat_map(Result::ok).map(|x| x.into_inner()) {
+        for (name, data) in values {
             match data {
-                Data::String(path) => {
+                Value::String(path) => {
                     create.insert(name, path);
                 }
-                Data::None => {
+                Value::None => {
                     delete.push(name);
                 }
-                unhandled => log::warn!(
-                    "Unhandled data for {}: {:?}",
-                    &name.to_string_lossy(),
-                    unhandled
-                ),
+                unhandled => log::warn!("Unhandled data for {}: {:?}", &name, unhandled),
             }
         }
 
-        Ok(Langs { create, delete })
+        for (name, data) in hidden_values {
+            match data {
+                Value::String(path) => {
+                    create.insert(name.clone(), path);
+                    hidden.insert(name);
+                }
+                unhandled => log::warn!("Unhandled data for {}: {:?}", &name, unhandled),
+            }
+        }
+
+        Langs {
+            scope,
+            create,
+            hidden,
+            delete,
+            origins: BTreeMap::new(),
+            tombstones: BTreeMap::new(),
+        }
+    }
+
+    pub fn register(
+        &mut self,
+        plan: &mut Plan,
+        names: &[String],
+        path: &Path,
+        origin_of: &dyn Fn(&str) -> Origin,
+    ) {
+        let key = spellers_key(self.scope);
+        let hidden = hidden_spellers_key(self.scope);
+        let display = path.to_string_lossy().to_string();
+
+        for name in names {
+            let origin = origin_of(name);
+            log::info!("Setting '{}' -> '{}' from {}", name, &display, &origin);
+            if origin.is_in_spellers_key() {
+                plan.set_value(&key, name, Value::String(display.clone()));
+                if self.hidden.remove(name) {
+                    plan.delete_value(&hidden, name);
+                }
+            } else {
+                // Keep it away from the hosts that read the Spellers key
+                plan.set_value(&hidden, name, Value::String(display.clone()));
+                if !self.hidden.contains(name)
+                    && (self.create.contains_key(name) || self.delete.contains(name))
+                {
+                    plan.delete_value(&key, name);
+                }
+                self.hidden.insert(name.clone());
+            }
+            self.delete.retain(|x| x != name);
+            self.create.insert(name.clone(), display.clone());
+            self.set_origin(plan, name, origin);
+            if self.tombstones.remove(name).is_some() {
+                plan.delete_value(&tombstones_key(self.scope), name);
+            }
+        }
+
+        log::info!(
+            "Successfully set {} language tags for '{}'.",
+            names.len(),
+            path.display()
+        );
+    }
+
+    pub fn deregister(&mut self, plan: &mut Plan, names: &[String], clock: &dyn Clock) {
+        let key = spellers_key(self.scope);
+        let now = clock::counter(clock);
+
+        for name in names {
+            log::info!("Setting '{}' -> <None>", name);
+            if self.hidden.remove(name) {
+                plan.delete_value(&hidden_spellers_key(self.scope), name);
+            }
+            plan.set_value(&key, name, Value::None);
+            plan.set_value(&tombstones_key(self.scope), name, Value::U32(now));
+            self.tombstones.insert(name.clone(), now);
+            self.create.remove(name);
+            if self.origins.remove(name).is_some() {
+                plan.delete_key(origins_key(self.scope).join(name));
+            }
+            if !self.delete.contains(name) {
+                self.delete.push(name.clone());
+            }
+        }
+
+        log::info!("Successfully unset {} language tags.", names.len());
+    }
+
+    /// Drops tombstones written more than `retention` ago, by which time Office
+    /// has had its chance to process their Delete records. Tombstones written
+    /// before they were dated are dated now. Returns the dropped tags.
+    pub fn collect_tombstones(
+        &mut self,
+        plan: &mut Plan,
+        retention: Duration,
+        clock: &dyn Clock,
+    ) -> Vec<String> {
+        let key = spellers_key(self.scope);
+        let dates = tombstones_key(self.scope);
+        let now = clock::counter(clock);
+        let retention: u32 = retention.as_secs().try_into().unwrap_or(u32::MAX);
+
+        let mut expired = vec![];
+        for name in self.delete.iter() {
+            match self.tombstones.get(name) {
+                Some(written) if now.saturating_sub(*written) >= retention => {
+                    expired.push(name.clone())
+                }
+                Some(_) => {}
+                None => {
+                    log::debug!("Dating tombstone for {}", name);
+                    plan.set_value(&dates, name, Value::U32(now));
+                    self.tombstones.insert(name.clone(), now);
+                }
+            }
+        }
+
+        for name in expired.iter() {
+            log::info!("Dropping tombstone for '{}'", name);
+            plan.delete_value(&key, name);
+            plan.delete_value(&dates, name);
+            self.delete.retain(|x| x != name);
+            self.tombstones.remove(name);
+        }
+
+        expired
+    }
+
+    /// Plans removing the Delete records of tombstones dropped by `collect_tombstones`.
+    /// Count is left alone, as there is nothing left for Office to do.
+    pub fn drop_delete_records(
+        &self,
+        registry: &dyn RegistryBackend,
+        plan: &mut Plan,
+        base_path: &str,
+        names: &[String],
+        options: &RefreshOptions,
+    ) -> Result<(), Error> {
+        for name in names {
+            let key = proof_tool_key(base_path, PATH_DELETE, name, options);
+            if registry.key_exists(&key)? {
+                log::debug!("Removing delete for {}", name);
+                plan.delete_key(key);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records where a registered tag came from, without touching its speller path.
+    pub fn set_origin(&mut self, plan: &mut Plan, name: &str, origin: Origin) {
+        origin.write(plan, &origins_key(self.scope).join(name));
+        self.origins.insert(name.to_string(), origin);
     }
 
-    pub fn refresh(&self, base_path: &str) -> Result<(), Error> {
+    /// Whether a registered tag is for `host`. Tags without an origin are for all hosts.
+    pub(crate) fn is_for(&self, name: &str, host: Host) -> bool {
+        self.origins
+            .get(name)
+            .map(|x| x.hosts.contains(&host))
+            .unwrap_or(true)
+    }
+
+    /// The registered tags that are for `host`, with their speller paths.
+    pub(crate) fn create_for(&self, host: Host) -> impl Iterator<Item = (&String, &String)> {
+        self.create
+            .iter()
+            .filter(move |(name, _)| self.is_for(name, host))
+    }
+
+    /// Describes where a registered tag came from, for logs.
+    fn origin_of(&self, name: &str) -> String {
+        match self.origins.get(name) {
+            Some(origin) => origin.to_string(),
+            None => "an unknown origin".to_string(),
+        }
+    }
+
+    /// Plans Create and Delete records for a User Settings path, skipping records
+    /// that are already correct. Tags that are not for Office get a Delete record
+    /// only if they still have a Create record. Count is only bumped if something changed or it is
+    /// older than `spellers_updated`, so Office does not reprocess unchanged overrides.
+    pub fn refresh(
+        &self,
+        registry: &dyn RegistryBackend,
+        plan: &mut Plan,
+        base_path: &str,
+        spellers_updated: Option<u32>,
+        options: &RefreshOptions,
+        clock: &dyn Clock,
+    ) -> Result<bool, Error> {
+        let dlls = &options.mso_dlls;
+        let mut changed = false;
+
         for (lang_id, speller_path) in self.create.iter() {
-            log::debug!("Adding create for {}", &lang_id.to_string_lossy());
-            add_create_key(
-                base_path,
-                &lang_id.to_string_lossy(),
-                &speller_path.to_string_lossy(),
-            )?;
+            if !self.is_for(lang_id, Host::Office) {
+                // Take back a Create record written before the speller left Office
+                if registry.key_exists(&proof_tool_key(base_path, PATH_CREATE, lang_id, options))? {
+                    log::info!("Adding delete for {}, which is not for Office", lang_id);
+                    add_delete_key(plan, base_path, lang_id, options);
+                    changed = true;
+                }
+                continue;
+            }
+
+            let values = [
+                ("LEX", speller_path.as_str()),
+                ("LEX64", speller_path.as_str()),
+                ("DLL", dlls.dll32.as_str()),
+                ("DLL64", dlls.dll64.as_str()),
+            ];
+            let key = proof_tool_key(base_path, PATH_CREATE, lang_id, options);
+            if registry.key_exists(&proof_tool_key(base_path, PATH_DELETE, lang_id, options))?
+                || !record_matches(registry, &key, &values)?
+            {
+                log::info!(
+                    "Adding create for {} from {}",
+                    lang_id,
+                    self.origin_of(lang_id)
+                );
+                add_create_key(plan, base_path, lang_id, speller_path, options);
+                changed = true;
+            }
         }
 
         for lang_id in self.delete.iter() {
-            log::debug!("Adding delete for {}", &lang_id.to_string_lossy());
-            add_delete_key(base_path, &lang_id.to_string_lossy())?;
+            let values = [("LEX", ""), ("LEX64", ""), ("DLL", ""), ("DLL64", "")];
+            let key = proof_tool_key(base_path, PATH_DELETE, lang_id, options);
+            if registry.key_exists(&proof_tool_key(base_path, PATH_CREATE, lang_id, options))?
+                || !record_matches(registry, &key, &values)?
+            {
+                log::debug!("Adding delete for {}", lang_id);
+                add_delete_key(plan, base_path, lang_id, options);
+                changed = true;
+            }
         }
 
-        log::debug!("Updating count key");
-        set_regkey_counter(base_path)?;
+        let base_key = KeyPath::new(Hive::LocalMachine, View::Registry64, base_path);
+        let outdated = match (registry.value(&base_key, "Count")?, spellers_updated) {
+            (Some(Value::U32(count)), Some(updated)) => count < updated,
+            (Some(Value::U32(_)), None) => false,
+            _ => true,
+        };
+
+        if changed || outdated {
+            log::debug!("Updating count key");
+            set_regkey_counter(registry, plan, base_path, spellers_updated, clock)?;
+        } else {
+            log::debug!("No changes for {}", base_path);
+        }
 
-        Ok(())
+        Ok(changed || outdated)
+    }
+
+    /// Plans the current user's Office overrides, skipping ones that are already
+    /// correct. Deregistered languages, and ones not for Office, simply have
+    /// their override removed.
+    pub fn refresh_user_overrides(
+        &self,
+        registry: &dyn RegistryBackend,
+        plan: &mut Plan,
+        options: &RefreshOptions,
+    ) -> Result<bool, Error> {
+        let dlls = &options.mso_dlls;
+        let base = user_overrides_key(options);
+        let mut changed = false;
+
+        for (lang_id, speller_path) in self.create_for(Host::Office) {
+            let values = [
+                ("LEX", speller_path.as_str()),
+                ("LEX64", speller_path.as_str()),
+                ("DLL", dlls.dll32.as_str()),
+                ("DLL64", dlls.dll64.as_str()),
+            ];
+            let key = base.join(lang_id);
+            if !record_matches(registry, &key, &values)? {
+                log::info!(
+                    "Adding override for {} from {}",
+                    lang_id,
+                    self.origin_of(lang_id)
+                );
+                plan.create_key(key.clone());
+                for (name, value) in values.iter() {
+                    plan.set_value(&key, name, Value::String(value.to_string()));
+                }
+                changed = true;
+            }
+        }
+
+        let excluded = self
+            .create
+            .keys()
+            .filter(|name| !self.is_for(name, Host::Office));
+        for lang_id in self.delete.iter().chain(excluded) {
+            // Leave overrides alone that another speller has since taken over
+            let key = base.join(lang_id);
+            let ours = matches!(
+                registry.value(&key, "DLL64")?,
+                Some(Value::String(dll)) if mso::is_divvunspell_mso(&dll)
+            );
+            if ours {
+                log::debug!("Removing override for {}", lang_id);
+                plan.delete_key(key);
+                changed = true;
+            }
+        }
+
+        Ok(changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryRegistry;
+    use crate::testing::FixedClock;
+
+    const BASE_PATH: &str = r"SOFTWARE\Microsoft\Office\16.0\User Settings\WinDivvun";
+
+    fn count(registry: &MemoryRegistry) -> Option<Value> {
+        let key = KeyPath::new(Hive::LocalMachine, View::Registry64, BASE_PATH);
+        registry.value(&key, "Count").unwrap()
+    }
+
+    #[test]
+    fn count_follows_the_clock() {
+        let registry = MemoryRegistry::new();
+        let mut plan = Plan::new();
+        set_regkey_counter(&registry, &mut plan, BASE_PATH, None, &FixedClock::at(1000)).unwrap();
+        plan.apply(&registry).unwrap();
+
+        assert_eq!(count(&registry), Some(Value::U32(1000)));
+    }
+
+    #[test]
+    fn count_increases_when_the_clock_goes_backwards() {
+        let registry = MemoryRegistry::new();
+        let clock = FixedClock::at(5000);
+        let mut plan = Plan::new();
+        set_regkey_counter(&registry, &mut plan, BASE_PATH, None, &clock).unwrap();
+        plan.apply(&registry).unwrap();
+
+        clock.set(1000);
+        let mut plan = Plan::new();
+        set_regkey_counter(&registry, &mut plan, BASE_PATH, None, &clock).unwrap();
+        plan.apply(&registry).unwrap();
+
+        assert_eq!(count(&registry), Some(Value::U32(5001)));
+    }
+
+    #[test]
+    fn old_plans_do_not_lower_count() {
+        let registry = MemoryRegistry::new();
+        let mut old = Plan::new();
+        set_regkey_counter(&registry, &mut old, BASE_PATH, None, &FixedClock::at(1000)).unwrap();
+
+        let mut plan = Plan::new();
+        set_regkey_counter(&registry, &mut plan, BASE_PATH, None, &FixedClock::at(2000)).unwrap();
+        plan.apply(&registry).unwrap();
+        old.apply(&registry).unwrap();
+
+        assert_eq!(count(&registry), Some(Value::U32(2001)));
+    }
+
+    #[test]
+    fn count_is_never_older_than_spellers_updated() {
+        let registry = MemoryRegistry::new();
+        let mut plan = Plan::new();
+        let clock = FixedClock::at(1000);
+        set_regkey_counter(&registry, &mut plan, BASE_PATH, Some(2000), &clock).unwrap();
+        plan.apply(&registry).unwrap();
+
+        assert_eq!(count(&registry), Some(Value::U32(2000)));
+    }
+
+    #[test]
+    fn tombstones_expire_after_the_retention() {
+        let registry = MemoryRegistry::new();
+        let clock = FixedClock::at(1000);
+        let retention = Duration::from_secs(100);
+
+        let mut langs = Langs::new(&registry, Scope::Machine).unwrap();
+        let mut plan = Plan::new();
+        langs.deregister(&mut plan, &["se".to_string()], &clock);
+        plan.apply(&registry).unwrap();
+
+        clock.set(1099);
+        let mut langs = Langs::new(&registry, Scope::Machine).unwrap();
+        let mut plan = Plan::new();
+        assert!(langs
+            .collect_tombstones(&mut plan, retention, &clock)
+            .is_empty());
+        assert_eq!(langs.delete, vec!["se".to_string()]);
+        assert!(plan.steps.is_empty());
+
+        clock.set(1100);
+        let mut langs = Langs::new(&registry, Scope::Machine).unwrap();
+        let mut plan = Plan::new();
+        assert_eq!(
+            langs.collect_tombstones(&mut plan, retention, &clock),
+            vec!["se".to_string()]
+        );
+        plan.apply(&registry).unwrap();
+
+        let langs = Langs::new(&registry, Scope::Machine).unwrap();
+        assert!(langs.delete.is_empty());
+        assert!(langs.tombstones.is_empty());
+    }
+
+    #[test]
+    fn undated_tombstones_are_dated_rather_than_dropped() {
+        let registry = MemoryRegistry::new();
+        let key = spellers_key(Scope::Machine);
+        registry.set_value(&key, "se", &Value::None).unwrap();
+
+        let clock = FixedClock::at(1000);
+        let mut langs = Langs::new(&registry, Scope::Machine).unwrap();
+        let mut plan = Plan::new();
+        let expired = langs.collect_tombstones(&mut plan, Duration::from_secs(0), &clock);
+        assert!(expired.is_empty());
+        plan.apply(&registry).unwrap();
+
+        assert_eq!(
+            registry
+                .value(&tombstones_key(Scope::Machine), "se")
+                .unwrap(),
+            Some(Value::U32(1000))
+        );
     }
 }
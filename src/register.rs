@@ -1,9 +1,17 @@
-use std::{collections::HashSet, convert::Infallible};
+use std::{collections::HashSet, convert::Infallible, path::Path, path::PathBuf};
 use unic_langid::{
     subtags::{Region, Script},
     LanguageIdentifier,
 };
 
+use crate::backend::{RegistryBackend, Value};
+use crate::clock::Clock;
+use crate::filesystem::FileSystem;
+use crate::format::Format;
+use crate::plan::{self, Plan};
+use crate::refresh::RefreshOptions;
+use crate::reg::Scope;
+
 #[derive(Debug, thiserror::Error)]
 pub(crate) enum Error {
     #[error("Invalid language tag")]
@@ -14,6 +22,21 @@ pub(crate) enum Error {
 
     #[error("Could not update registry")]
     Registry(#[from] crate::reg::Error),
+
+    #[error("An IO error occurred")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid speller file")]
+    InvalidSpeller(#[from] crate::format::Error),
+
+    #[error("The installed DivvunSpell cannot load {0} spellers")]
+    UnsupportedFormat(Format),
+
+    #[error("Failed to plan refresh")]
+    Refresh(#[from] crate::refresh::Error),
+
+    #[error("Failed to apply plan")]
+    Plan(#[from] plan::Error),
 }
 
 impl From<Infallible> for Error {
@@ -52,7 +75,7 @@ pub(crate) fn derive_lang_id_keys(mut lang_id: LanguageIdentifier) -> Result<Vec
                 }
                 None => {
                     let script = iso639::script::get(lang_id.language.as_str())
-                        .ok_or_else(|| Error::NoDefaultScript)?
+                        .ok_or(Error::NoDefaultScript)?
                         .script;
                     log::info!("Using derived default script: {}", script);
                     lang_id.script = Script::from_bytes(script.as_bytes()).ok();
@@ -85,16 +108,107 @@ pub(crate) fn derive_lang_id_keys(mut lang_id: LanguageIdentifier) -> Result<Vec
     Ok(keys)
 }
 
-// pub(crate) fn register(args: RegisterArgs) -> Result<(), Error> {
-//     let lang_id: LanguageIdentifier = args.tag.parse()?;
-//     log::info!("Registering speller for '{}'...", &lang_id);
-
-//     let keys = derive_lang_id_keys(lang_id)?;
-//     crate::reg::register_langs(&keys, &*args.path)?;
-
-//     crate::refresh::refresh();
-
-//     log::info!("Registration complete!");
+/// Registers `path` for `tag` and everything derived from it, stored apart from
+/// the package registrations so it survives later refreshes.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn register(
+    registry: &dyn RegistryBackend,
+    fs: &dyn FileSystem,
+    clock: &dyn Clock,
+    speller_roots: &[PathBuf],
+    scope: Scope,
+    options: &RefreshOptions,
+    tag: &str,
+    path: &Path,
+) -> Result<(), Error> {
+    let lang_id: LanguageIdentifier = tag.parse()?;
+    log::info!("Registering speller for '{}'...", &lang_id);
+
+    // Fail early rather than storing a tag refresh cannot use
+    derive_lang_id_keys(lang_id.clone())?;
+
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()?.join(path)
+    };
+    let format = Format::from_path(&path).unwrap_or(Format::Zhfst);
+    if !options.speller_formats.contains(&format) {
+        return Err(Error::UnsupportedFormat(format));
+    }
+    crate::format::validate(fs, &path, format)?;
+
+    let tag = lang_id.to_string();
+    let display = path.to_string_lossy().to_string();
+    let mut plan = Plan::new();
+    let mut manual = crate::reg::manual_registrations(registry, scope)?;
+
+    log::info!("Storing manual registration '{}' -> '{}'", &tag, &display);
+    plan.set_value(
+        &crate::reg::manual_spellers_key(scope),
+        &tag,
+        Value::String(display.clone()),
+    );
+    manual.insert(tag, Some(display));
+
+    crate::refresh::plan_with(
+        registry,
+        fs,
+        clock,
+        speller_roots,
+        scope,
+        &manual,
+        options,
+        &mut plan,
+    )?;
+    plan.apply(registry)?;
+
+    log::info!("Registration complete!");
+
+    Ok(())
+}
 
-//     Ok(())
-// }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, FixedClock};
+
+    #[test]
+    fn manual_registrations_survive_refresh() {
+        let (registry, mut fs) = testing::machine();
+        testing::add_package(
+            &mut fs,
+            "se",
+            "[spellers]\nse = \"se.zhfst\"\n",
+            &["se.zhfst"],
+        );
+        let path = std::env::temp_dir().join("sma.zhfst");
+        testing::add_zhfst(&mut fs, &path);
+        let options = RefreshOptions::default();
+        let clock = FixedClock::at(1000);
+        let roots = testing::roots();
+
+        register(
+            &registry,
+            &fs,
+            &clock,
+            &roots,
+            Scope::Machine,
+            &options,
+            "sma",
+            &path,
+        )
+        .unwrap();
+
+        clock.set(2000);
+        crate::refresh::plan(&registry, &fs, &clock, &roots, Scope::Machine, &options)
+            .unwrap()
+            .apply(&registry)
+            .unwrap();
+
+        let spellers = crate::reg::spellers_key(Scope::Machine);
+        let expected = Some(Value::String(path.to_string_lossy().to_string()));
+        assert_eq!(registry.value(&spellers, "sma").unwrap(), expected);
+        assert!(registry.value(&spellers, "se").unwrap().is_some());
+    }
+}
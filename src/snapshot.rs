@@ -0,0 +1,399 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::ErrorKind,
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::backend::{Hive, KeyPath, RegistryBackend, View};
+use crate::capture::{capture_key, CapturedKey};
+use crate::clock::Clock;
+use crate::plan::{self, Plan};
+use crate::refresh::{self, RefreshOptions};
+use crate::reg::{self, Langs, Scope};
+
+/// How many snapshots are kept before the oldest ones are removed.
+const MAX_SNAPSHOTS: usize = 10;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum Error {
+    #[error("A registry error occurred")]
+    Registry(#[from] reg::Error),
+
+    #[error("An IO error occurred")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid snapshot file")]
+    Json(#[from] serde_json::Error),
+
+    #[error("No snapshot found with id {0}")]
+    NotFound(String),
+
+    #[error("Failed to apply plan")]
+    Plan(#[from] plan::Error),
+
+    #[error("Could not find the user's app data directory")]
+    DataDir(#[from] pathos::Error),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SnapshotRoot {
+    key: KeyPath,
+    /// Empty if the root did not exist when the snapshot was taken.
+    keys: Vec<CapturedKey>,
+}
+
+/// The state of every registry key spelli owns in one scope: the Spellers key
+/// and, for the machine, the WinDivvun User Settings subtree of every Office version.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Snapshot {
+    pub(crate) id: String,
+    roots: Vec<SnapshotRoot>,
+}
+
+fn owned_roots(scope: Scope, options: &RefreshOptions) -> Vec<KeyPath> {
+    match scope {
+        Scope::Machine => vec![reg::spellers_key(scope), reg::manual_spellers_key(scope)]
+            .into_iter()
+            .chain(
+                refresh::all_user_settings_paths(options)
+                    .into_iter()
+                    .map(|path| KeyPath::new(Hive::LocalMachine, View::Registry64, path)),
+            )
+            .collect(),
+        // Per-user overrides live among those of other vendors, so they are
+        // recomputed from the Spellers key on restore instead.
+        Scope::User => vec![reg::spellers_key(scope), reg::manual_spellers_key(scope)],
+    }
+}
+
+impl Snapshot {
+    pub(crate) fn take(
+        registry: &dyn RegistryBackend,
+        scope: Scope,
+        options: &RefreshOptions,
+        clock: &dyn Clock,
+    ) -> Result<Snapshot, reg::Error> {
+        let id = chrono::DateTime::<chrono::Utc>::from(clock.now())
+            .format("%Y%m%dT%H%M%S%3fZ")
+            .to_string();
+        let mut roots = vec![];
+
+        for key in owned_roots(scope, options) {
+            let mut keys = vec![];
+            capture_key(registry, &key, true, &mut keys)?;
+            roots.push(SnapshotRoot { key, keys });
+        }
+
+        Ok(Snapshot { id, roots })
+    }
+
+    /// Plans putting every owned key back the way it was, then bumps Count on
+    /// each User Settings path so Office picks the change up. Per-user overrides
+    /// are brought in line with the restored Spellers key.
+    pub(crate) fn restore(
+        &self,
+        registry: &dyn RegistryBackend,
+        plan: &mut Plan,
+        options: &RefreshOptions,
+        clock: &dyn Clock,
+    ) -> Result<(), reg::Error> {
+        let spellers_updated = reg::spellers_updated(registry, Scope::Machine)?;
+
+        for root in self.roots.iter() {
+            if root.key == reg::spellers_key(Scope::User) {
+                self.restore_user_overrides(registry, plan, root, options)?;
+            }
+
+            if root.keys.is_empty() && !registry.key_exists(&root.key)? {
+                continue;
+            }
+
+            let is_user_settings = root.key.hive == Hive::LocalMachine
+                && refresh::all_user_settings_paths(options).contains(&root.key.path);
+            let base_path = &root.key.path;
+
+            // Office has already copied any Create records added since the snapshot,
+            // so they need explicit Delete records rather than just disappearing.
+            let added = if is_user_settings {
+                let created = reg::create_records_key(base_path, options);
+                let current = match registry.subkeys(&created) {
+                    Err(reg::Error::NotFound(_)) => vec![],
+                    result => result?,
+                };
+                let prefix = format!(r"{}\", created.path.to_lowercase());
+                let previous = root
+                    .keys
+                    .iter()
+                    .filter_map(|x| {
+                        let path = x.key.path.to_lowercase();
+                        path.strip_prefix(&prefix)
+                            .filter(|rest| !rest.contains('\\'))
+                            .map(str::to_string)
+                    })
+                    .collect::<Vec<_>>();
+
+                current
+                    .into_iter()
+                    .filter(|tag| !previous.contains(&tag.to_lowercase()))
+                    .collect()
+            } else {
+                vec![]
+            };
+
+            plan.delete_key(root.key.clone());
+            for captured in root.keys.iter() {
+                plan.create_key(captured.key.clone());
+                for (name, value) in captured.values.iter() {
+                    plan.set_value(&captured.key, name, value.clone());
+                }
+            }
+
+            if is_user_settings {
+                for tag in added.iter() {
+                    log::info!("Marking '{}' as deleted in {}", tag, base_path);
+                    reg::add_delete_key(plan, base_path, tag, options);
+                }
+                reg::set_regkey_counter(registry, plan, base_path, spellers_updated, clock)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn restore_user_overrides(
+        &self,
+        registry: &dyn RegistryBackend,
+        plan: &mut Plan,
+        root: &SnapshotRoot,
+        options: &RefreshOptions,
+    ) -> Result<(), reg::Error> {
+        let values = |key: &KeyPath| {
+            root.keys
+                .iter()
+                .find(|x| x.key == *key)
+                .map(|x| x.values.clone())
+                .unwrap_or_default()
+        };
+        let mut langs = Langs::from_values(
+            Scope::User,
+            values(&root.key),
+            values(&reg::hidden_spellers_key(Scope::User)),
+        );
+
+        // Anything registered since the snapshot needs its override removed
+        for tag in Langs::new(registry, Scope::User)?.create.keys() {
+            if !langs.create.contains_key(tag) && !langs.delete.contains(tag) {
+                langs.delete.push(tag.clone());
+            }
+        }
+
+        langs.refresh_user_overrides(registry, plan, options)?;
+        Ok(())
+    }
+}
+
+/// Snapshots on disk, named by id. Ids sort chronologically.
+pub(crate) struct SnapshotStore {
+    dir: PathBuf,
+}
+
+impl SnapshotStore {
+    pub(crate) fn new(scope: Scope) -> Result<SnapshotStore, Error> {
+        let dir = match scope {
+            Scope::Machine => pathos::system::app_data_dir("WinDivvun"),
+            Scope::User => pathos::user::app_data_dir("WinDivvun")?,
+        };
+
+        Ok(SnapshotStore {
+            dir: dir.join("snapshots"),
+        })
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    pub(crate) fn ids(&self) -> Result<Vec<String>, Error> {
+        if !self.dir.exists() {
+            return Ok(vec![]);
+        }
+
+        let mut ids = std::fs::read_dir(&self.dir)?
+            .filter_map(Result::ok)
+            .map(|x| x.path())
+            .filter(|x| x.extension().map(|ext| ext == "json") == Some(true))
+            .filter_map(|x| x.file_stem().map(|x| x.to_string_lossy().to_string()))
+            .collect::<Vec<_>>();
+        ids.sort();
+        Ok(ids)
+    }
+
+    pub(crate) fn load(&self, id: &str) -> Result<Snapshot, Error> {
+        let path = self.path(id);
+        if !path.exists() {
+            return Err(Error::NotFound(id.to_string()));
+        }
+        Ok(serde_json::from_reader(File::open(path)?)?)
+    }
+
+    pub(crate) fn latest(&self) -> Result<Option<Snapshot>, Error> {
+        match self.ids()?.last() {
+            Some(id) => Ok(Some(self.load(id)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Writes `snapshot`, suffixing its id if another snapshot was taken in the
+    /// same millisecond.
+    pub(crate) fn save(&self, snapshot: &mut Snapshot) -> Result<PathBuf, Error> {
+        std::fs::create_dir_all(&self.dir)?;
+        let base_id = snapshot.id.clone();
+        let mut suffix = 0;
+        let (path, file) = loop {
+            let path = self.path(&snapshot.id);
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => break (path, file),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    suffix += 1;
+                    snapshot.id = format!("{}-{:03}", base_id, suffix);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+        serde_json::to_writer_pretty(file, snapshot)?;
+
+        let ids = self.ids()?;
+        if ids.len() > MAX_SNAPSHOTS {
+            for id in &ids[..ids.len() - MAX_SNAPSHOTS] {
+                log::debug!("Removing old snapshot {}", id);
+                std::fs::remove_file(self.path(id))?;
+            }
+        }
+
+        Ok(path)
+    }
+}
+
+/// Snapshots the current state before spelli changes anything.
+pub(crate) fn save_current(
+    registry: &dyn RegistryBackend,
+    scope: Scope,
+    options: &RefreshOptions,
+    clock: &dyn Clock,
+) -> Result<Snapshot, Error> {
+    let mut snapshot = Snapshot::take(registry, scope, options, clock)?;
+    let path = SnapshotStore::new(scope)?.save(&mut snapshot)?;
+    log::info!("Saved snapshot {} to {}", &snapshot.id, path.display());
+    Ok(snapshot)
+}
+
+/// Puts back snapshot `id`, or the latest one. The state being replaced is not
+/// snapshotted, so the latest snapshot stays the one to go back to, restoring
+/// twice does not undo the first restore, and older snapshots are not pushed
+/// out by the limit.
+pub(crate) fn restore(
+    registry: &dyn RegistryBackend,
+    clock: &dyn Clock,
+    options: &RefreshOptions,
+    scope: Scope,
+    id: Option<&str>,
+) -> Result<(), Error> {
+    let store = SnapshotStore::new(scope)?;
+    let snapshot = match id {
+        Some(id) => store.load(id)?,
+        None => store
+            .latest()?
+            .ok_or_else(|| Error::NotFound("<latest>".to_string()))?,
+    };
+
+    log::info!("Restoring snapshot {}", &snapshot.id);
+    let mut plan = Plan::new();
+    snapshot.restore(registry, &mut plan, options, clock)?;
+    plan.apply(registry)?;
+
+    log::info!("Restored snapshot {}", &snapshot.id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::Value;
+    use crate::testing::{self, FixedClock};
+
+    #[test]
+    fn ids_come_from_the_clock() {
+        let (registry, _) = testing::machine();
+        let options = RefreshOptions::default();
+        let snapshot =
+            Snapshot::take(&registry, Scope::Machine, &options, &FixedClock::at(1000)).unwrap();
+        assert_eq!(snapshot.id, "20200101T001640000Z");
+    }
+
+    #[test]
+    fn snapshots_taken_in_the_same_millisecond_are_kept() {
+        let (registry, _) = testing::machine();
+        let options = RefreshOptions::default();
+        let clock = FixedClock::at(1000);
+        let store = SnapshotStore {
+            dir: testing::temp_dir("snapshots"),
+        };
+
+        let mut first = Snapshot::take(&registry, Scope::Machine, &options, &clock).unwrap();
+        let mut second = first.clone();
+        store.save(&mut first).unwrap();
+        store.save(&mut second).unwrap();
+        let ids = store.ids().unwrap();
+        std::fs::remove_dir_all(&store.dir).unwrap();
+
+        assert_eq!(ids, vec![first.id, second.id.clone()]);
+        assert_eq!(second.id, "20200101T001640000Z-001");
+    }
+
+    #[test]
+    fn restore_takes_back_later_registrations() {
+        let (registry, mut fs) = testing::machine();
+        let options = RefreshOptions::default();
+        let clock = FixedClock::at(1000);
+        let snapshot = Snapshot::take(&registry, Scope::Machine, &options, &clock).unwrap();
+
+        testing::add_package(
+            &mut fs,
+            "se",
+            "[spellers]\nse = \"se.zhfst\"\n",
+            &["se.zhfst"],
+        );
+        refresh::plan(
+            &registry,
+            &fs,
+            &clock,
+            &testing::roots(),
+            Scope::Machine,
+            &options,
+        )
+        .unwrap()
+        .apply(&registry)
+        .unwrap();
+
+        clock.set(2000);
+        let mut plan = Plan::new();
+        snapshot
+            .restore(&registry, &mut plan, &options, &clock)
+            .unwrap();
+        plan.apply(&registry).unwrap();
+
+        let spellers = reg::spellers_key(Scope::Machine);
+        assert_eq!(registry.value(&spellers, "se").unwrap(), None);
+        for path in refresh::user_settings_paths(&registry, &options) {
+            let deleted = reg::delete_records_key(&path, &options).join("se");
+            assert!(registry.key_exists(&deleted).unwrap());
+            let base = KeyPath::new(Hive::LocalMachine, View::Registry64, &path);
+            assert_eq!(
+                registry.value(&base, "Count").unwrap(),
+                Some(Value::U32(2000))
+            );
+        }
+    }
+}
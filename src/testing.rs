@@ -0,0 +1,155 @@
+//! Fixtures shared by the unit tests.
+
+use std::{
+    cell::Cell,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::backend::{Hive, KeyPath, MemoryRegistry, RegistryBackend, Value, View};
+use crate::clock::Clock;
+use crate::filesystem::MemoryFileSystem;
+use crate::package::SPELLER_TOML;
+use crate::plan::{Plan, Step};
+use crate::{refresh, reg};
+
+/// 2020-01-01, where Count starts counting.
+const COUNTER_EPOCH: u64 = 1577836800;
+
+pub(crate) const SPELLER_ROOT: &str = r"C:\Program Files\WinDivvun\Spellers";
+
+/// A clock that reads whatever the test last set it to.
+pub(crate) struct FixedClock(Cell<SystemTime>);
+
+impl FixedClock {
+    /// A clock reading `counter` in Count units, seconds since 2020-01-01.
+    pub(crate) fn at(counter: u32) -> FixedClock {
+        let clock = FixedClock(Cell::new(UNIX_EPOCH));
+        clock.set(counter);
+        clock
+    }
+
+    pub(crate) fn set(&self, counter: u32) {
+        self.0
+            .set(UNIX_EPOCH + Duration::from_secs(COUNTER_EPOCH + u64::from(counter)));
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> SystemTime {
+        self.0.get()
+    }
+}
+
+/// The speller roots of `machine`.
+pub(crate) fn roots() -> Vec<PathBuf> {
+    vec![PathBuf::from(SPELLER_ROOT)]
+}
+
+/// Adds a package in `SPELLER_ROOT\<name>` with `speller_toml` and a valid
+/// ZHFST for every file in `zhfsts`.
+pub(crate) fn add_package(
+    fs: &mut MemoryFileSystem,
+    name: &str,
+    speller_toml: &str,
+    zhfsts: &[&str],
+) -> PathBuf {
+    let dir = Path::new(SPELLER_ROOT).join(name);
+    fs.add_dir(&dir);
+    fs.add_file(&dir.join(SPELLER_TOML), Some(speller_toml.to_string()));
+    for file in zhfsts {
+        add_zhfst(fs, &dir.join(file));
+    }
+    dir
+}
+
+/// Adds a valid ZHFST at `path`.
+pub(crate) fn add_zhfst(fs: &mut MemoryFileSystem, path: &Path) {
+    let entries = [
+        "index.xml",
+        "acceptor.default.hfst",
+        "errmodel.default.hfst",
+    ];
+    fs.add_speller_file(
+        path,
+        Some(entries.iter().map(|x| x.to_string()).collect()),
+        None,
+        Some(b"PK\x03\x04".to_vec()),
+    );
+}
+
+/// An empty registry, and a filesystem with the default DivvunSpell MSO DLLs
+/// and an empty speller root.
+pub(crate) fn machine() -> (MemoryRegistry, MemoryFileSystem) {
+    let mut fs = MemoryFileSystem::new();
+    fs.add_dir(Path::new(SPELLER_ROOT));
+    fs.add_file(Path::new(reg::DIVVUNSPELL_MSO_32), None);
+    fs.add_file(Path::new(reg::DIVVUNSPELL_MSO_64), None);
+    (MemoryRegistry::new(), fs)
+}
+
+/// Adds a LibreOffice installation, returning its unopkg.
+pub(crate) fn add_libreoffice(registry: &MemoryRegistry, fs: &mut MemoryFileSystem) -> PathBuf {
+    let install_dir = r"C:\Program Files\LibreOffice";
+    let key = KeyPath::new(Hive::LocalMachine, View::Registry64, refresh::KEY_UNINSTALL)
+        .join("LibreOffice");
+    let values = [
+        ("DisplayName", "LibreOffice 7.6"),
+        ("InstallLocation", install_dir),
+    ];
+    for (name, value) in values.iter() {
+        registry
+            .set_value(&key, name, &Value::String(value.to_string()))
+            .unwrap();
+    }
+
+    let unopkg = Path::new(install_dir).join("program").join("unopkg.com");
+    fs.add_file(&unopkg, None);
+    unopkg
+}
+
+/// Adds an MSI install of Office 2016, whose User Settings are under
+/// `SOFTWARE\Microsoft\Office\16.0`.
+pub(crate) fn add_office(registry: &MemoryRegistry) {
+    let key = KeyPath::new(Hive::LocalMachine, View::Registry64, refresh::KEY_UNINSTALL)
+        .join("Office16.PROPLUS");
+    let values = [
+        ("Publisher", "Microsoft Corporation"),
+        ("DisplayName", "Microsoft Office Professional Plus 2016"),
+        ("DisplayVersion", "16.0.4266.1001"),
+    ];
+    for (name, value) in values.iter() {
+        registry
+            .set_value(&key, name, &Value::String(value.to_string()))
+            .unwrap();
+    }
+}
+
+/// Applies the registry steps of `plan`, leaving out the ones that would
+/// touch the real filesystem or run programs.
+pub(crate) fn apply_registry_steps(plan: &Plan, registry: &MemoryRegistry) {
+    let steps = plan
+        .steps
+        .iter()
+        .filter(|x| {
+            !matches!(
+                x,
+                Step::CreateDir { .. }
+                    | Step::WriteFile { .. }
+                    | Step::RemoveFile { .. }
+                    | Step::Unopkg { .. }
+            )
+        })
+        .cloned()
+        .collect();
+    Plan { steps }.apply(registry).unwrap();
+}
+
+/// A directory of its own under the system temp directory, for tests that
+/// apply file steps.
+pub(crate) fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("spelli-test-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,50 @@
+use std::path::{Path, PathBuf};
+
+use crate::filesystem::FileSystem;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum Error {
+    #[error("Speller file not found: {0}")]
+    NotFound(PathBuf),
+
+    #[error("Speller file is not a ZHFST archive: {0}")]
+    NotArchive(PathBuf, #[source] std::io::Error),
+
+    #[error("ZHFST archive {0} has no {1}")]
+    MissingEntry(PathBuf, &'static str),
+}
+
+/// Checks `path` is a ZHFST archive: a zip with an index.xml, an acceptor and
+/// an error model. Only the entry names are read.
+pub(crate) fn validate(fs: &dyn FileSystem, path: &Path) -> Result<(), Error> {
+    if !fs.exists(path) || fs.is_dir(path) {
+        return Err(Error::NotFound(path.to_path_buf()));
+    }
+
+    let entries = fs
+        .archive_entries(path)
+        .map_err(|e| Error::NotArchive(path.to_path_buf(), e))?;
+    let has = |prefix: &str, suffix: &str| {
+        entries
+            .iter()
+            .any(|x| x.starts_with(prefix) && x.ends_with(suffix))
+    };
+
+    if !entries.iter().any(|x| x == "index.xml") {
+        return Err(Error::MissingEntry(path.to_path_buf(), "index.xml"));
+    }
+    if !has("acceptor.", ".hfst") {
+        return Err(Error::MissingEntry(
+            path.to_path_buf(),
+            "acceptor (acceptor.*.hfst)",
+        ));
+    }
+    if !has("errmodel.", ".hfst") {
+        return Err(Error::MissingEntry(
+            path.to_path_buf(),
+            "error model (errmodel.*.hfst)",
+        ));
+    }
+
+    Ok(())
+}